@@ -1,5 +1,5 @@
 #![cfg_attr(not(feature = "std"), no_std, no_main)]
-pub use self::erc20::{Erc20, Erc20Ref};
+pub use self::erc20::{Erc20, Erc20Ref, Error};
 #[ink::contract]
 mod erc20 {
     use ink::storage::Mapping;
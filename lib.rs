@@ -5,6 +5,57 @@ use ink::{env::Environment, prelude::vec::Vec};
 type DefaultAccountId = <ink::env::DefaultEnvironment as Environment>::AccountId;
 type DefaultBalance = <ink::env::DefaultEnvironment as Environment>::Balance;
 
+/// Function ids of the PSP22 chain extension methods.
+///
+/// `#[ink(extension = ..)]` only accepts literals, so the ids are repeated on the
+/// `Psp22Extension` trait below. A fork targeting a runtime that assigns different ids
+/// must update both places; the unit tests dispatch on these constants and fail if the
+/// two ever disagree.
+pub mod extension_ids {
+    pub const TOKEN_NAME: u32 = 0x3d26;
+    pub const TOKEN_SYMBOL: u32 = 0x3420;
+    pub const TOKEN_DECIMALS: u32 = 0x7271;
+    pub const TOTAL_SUPPLY: u32 = 0x162d;
+    pub const BALANCE_OF: u32 = 0x6568;
+    pub const ALLOWANCE: u32 = 0x4d47;
+    pub const TRANSFER: u32 = 0xdb20;
+    pub const TRANSFER_FROM: u32 = 0x54b3;
+    pub const APPROVE: u32 = 0xb20f;
+    pub const INCREASE_ALLOWANCE: u32 = 0x96d6;
+    pub const DECREASE_ALLOWANCE: u32 = 0xfecb;
+
+    /// Every extension method id, in declaration order.
+    pub const ALL: [u32; 11] = [
+        TOKEN_NAME,
+        TOKEN_SYMBOL,
+        TOKEN_DECIMALS,
+        TOTAL_SUPPLY,
+        BALANCE_OF,
+        ALLOWANCE,
+        TRANSFER,
+        TRANSFER_FROM,
+        APPROVE,
+        INCREASE_ALLOWANCE,
+        DECREASE_ALLOWANCE,
+    ];
+
+    /// The selector of the contract message forwarding to each extension method, in
+    /// the order of `ALL`.
+    pub const SELECTORS: [([u8; 4], u32); 11] = [
+        ([0x3d, 0x26, 0x1b, 0xd4], TOKEN_NAME),
+        ([0x34, 0x20, 0x5b, 0xe5], TOKEN_SYMBOL),
+        ([0x72, 0x71, 0xb7, 0x82], TOKEN_DECIMALS),
+        ([0x16, 0x2d, 0xf8, 0xc2], TOTAL_SUPPLY),
+        ([0x65, 0x68, 0x38, 0x2f], BALANCE_OF),
+        ([0x4d, 0x47, 0xd9, 0x21], ALLOWANCE),
+        ([0xdb, 0x20, 0xf9, 0xf5], TRANSFER),
+        ([0x54, 0xb3, 0xc7, 0x6e], TRANSFER_FROM),
+        ([0xb2, 0x0f, 0x1b, 0xbd], APPROVE),
+        ([0x96, 0xd6, 0xb5, 0x7a], INCREASE_ALLOWANCE),
+        ([0xfe, 0xcb, 0x57, 0xd5], DECREASE_ALLOWANCE),
+    ];
+}
+
 #[ink::chain_extension]
 pub trait Psp22Extension {
     type ErrorCode = Psp22Error;
@@ -69,28 +120,254 @@ pub trait Psp22Extension {
     ) -> Result<()>;
 }
 
-#[derive(Debug, scale::Encode, scale::Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
 #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
 pub enum Psp22Error {
     TotalSupplyFailed,
+    /// Returned if no asset pair is registered for the asset id.
+    AssetPairNotFound,
+    /// Returned if pulling the ERC20 from the caller failed.
+    TransferFromFailed,
+    /// Returned if the caller is not the contract owner.
+    NotOwner,
+    /// Returned if the asset of the pair does not wrap the native currency.
+    NotNativeWrapper,
+    /// Returned if sending native currency out of the contract failed.
+    NativeTransferFailed,
+    /// Returned if the allowlist is enabled and the caller is not on it.
+    NotAllowlisted,
+    /// Returned if the caller is on the blocklist.
+    Blocked,
+    /// Returned if the swap amount exceeds the pair's per-swap maximum.
+    AboveMaxSwap,
+    /// Returned if the pair does not allow partially filled swaps.
+    PartialFillNotAllowed,
+    /// Returned if the decimals of the backing ERC20 are unknown.
+    Erc20DecimalsUnknown,
+    /// Returned if a rate has a zero numerator or denominator.
+    InvalidRate,
+    /// Returned if computing a swap output overflowed.
+    Overflow,
+    /// Returned if a swap would pay out nothing.
+    ZeroOutput,
+    /// Returned if a fee exceeds 100%.
+    InvalidFee,
+    /// Returned if swaps are paused.
+    Paused,
+    /// Returned if the caller is neither the owner nor the pauser.
+    NotPauser,
+    /// Returned if the ERC20 does not back the asset.
+    UnknownBacking,
+    /// Returned if a swap would pay out less than the caller's minimum.
+    SlippageExceeded,
+    /// Returned if the contract escrows too little ERC20 for the asset.
+    InsufficientEscrow,
+    /// Returned if sending ERC20 out of the contract failed.
+    Erc20TransferFailed,
+    /// Returned if a batch holds more than the allowed number of entries.
+    BatchTooLarge,
+    /// Returned if the pair's price oracle could not be queried.
+    OracleFailed,
+    /// Returned if the oracle rate is older than the pair allows.
+    StaleOracle,
+    /// Returned if wrapping native currency into the backing ERC20 failed.
+    WrapFailed,
+    /// Returned if a request with the same idempotency key was already processed.
+    DuplicateRequest,
+    /// Returned if the contract still escrows ERC20 for the asset.
+    EscrowNotEmpty,
+    /// Returned if the runtime reported a token name or symbol above
+    /// `MAX_METADATA_LEN` bytes.
+    MetadataTooLong,
+    /// Returned if a split share exceeds 100%.
+    InvalidSplit,
+    /// Returned if the caller swapped too recently.
+    RateLimited,
+    /// Returned if the caller approved this contract to spend too little ERC20.
+    InsufficientAllowance,
+    /// Returned if swaps of the asset are temporarily paused.
+    AssetPaused,
+    /// Returned if swaps of the asset are disabled.
+    AssetDisabled,
+    /// Returned if pulling ERC20 left this contract's balance unchanged.
+    UnexpectedTransferAmount,
+    /// Returned if the contract holds too little of the asset outside accrued fees.
+    InsufficientLiquidity,
+    /// Returned if the caller is not the proposed owner.
+    NotPendingOwner,
+    /// Returned if the ownership proposal was not accepted within the grace period.
+    ProposalExpired,
+    /// Returned if the decimals of the asset and its ERC20 differ by more than
+    /// amounts can be scaled by.
+    DecimalsOutOfRange,
+    /// Returned if the operation requires swaps to be paused.
+    NotPaused,
+    /// Returned if the oracle rate deviates from the pair's reference rate by more
+    /// than the allowed band.
+    PriceOutOfBand,
+    /// Returned if a large swap of the asset is still cooling down.
+    CooldownActive,
+    /// Returned if the contract was permanently disabled by `self_destruct_mode`.
+    Terminated,
+    /// Returned if the backing ERC20 does not implement `Erc20Trait::nonces`.
+    PermitUnsupported,
+    /// Returned if a multi-hop route has no hops or more than `MAX_HOPS`.
+    InvalidRoute,
+    /// Returned if a withdrawal would leave less native currency than the reserve.
+    ReserveViolation,
+    /// Returned if the recipient of `swap_and_call` failed to handle the callback.
+    CallbackFailed,
+    /// Returned if a token name is not valid UTF-8.
+    InvalidUtf8,
+    /// Returned if the asset id lies in the range reserved for system assets.
+    ReservedAssetId,
+    /// Returned if the timed approval the contract would spend has expired.
+    ApprovalExpired,
+    /// Returned if the entry at `index` of a batch failed.
+    BatchItemFailed {
+        index: u32,
+    },
+    /// Returned if a swap is attempted while `swap_and_call` holds the reentrancy
+    /// lock.
+    Reentrancy,
+    /// Returned if the ERC20 already backs another asset while backings must be
+    /// unique.
+    Erc20AlreadyBound,
+    /// Returned if a timed approval names a spender other than this contract.
+    UnsupportedSpender,
+    /// Returned if the caller spends an allowance another account granted this
+    /// contract.
+    NotAllowanceHolder,
+    /// Returned if the caller of a referred swap names itself as the referrer.
+    SelfReferral,
 }
 
 pub type Result<T> = core::result::Result<T, Psp22Error>;
 
+impl Psp22Error {
+    /// Returns the variant index of the error, i.e. the first byte of its SCALE
+    /// encoding.
+    pub fn discriminant(&self) -> u8 {
+        scale::Encode::using_encoded(self, |bytes| bytes[0])
+    }
+}
+
 impl From<scale::Error> for Psp22Error {
     fn from(_: scale::Error) -> Self {
         panic!("encountered unexpected invalid SCALE encoding")
     }
 }
 
+/// Non-zero status codes reported by the chain extension, along with the error each
+/// one maps to. A status code of `0` signals success.
+const STATUS_CODES: [(u32, Psp22Error); 1] = [(1, Psp22Error::TotalSupplyFailed)];
+
 impl ink::env::chain_extension::FromStatusCode for Psp22Error {
     fn from_status_code(status_code: u32) -> core::result::Result<(), Self> {
-        match status_code {
-            0 => Ok(()),
-            1 => Err(Self::TotalSupplyFailed),
-            _ => panic!("encountered unknown status code"),
+        if status_code == 0 {
+            return Ok(());
         }
+        match STATUS_CODES.iter().find(|(code, _)| *code == status_code) {
+            Some((_, error)) => Err(error.clone()),
+            None => panic!("encountered unknown status code"),
+        }
+    }
+}
+
+/// Returns the chain extension status code that maps to the error with the given
+/// discriminant.
+///
+/// Returns `0` for errors raised by the contract itself rather than the runtime.
+pub fn status_code_for(error: u8) -> u32 {
+    STATUS_CODES
+        .iter()
+        .find(|(_, mapped)| mapped.discriminant() == error)
+        .map_or(0, |(code, _)| *code)
+}
+
+/// Builds a reduced `(numerator, denominator)` rate from the decimal
+/// `whole.frac`, where `frac` has `frac_decimals` digits: `1.25` is
+/// `rate_from_parts(1, 25, 2)` and yields `(5, 4)`.
+///
+/// # Panics
+///
+/// Panics if `frac_decimals` exceeds 19 or `frac` has more than `frac_decimals`
+/// digits.
+pub fn rate_from_parts(whole: u64, frac: u64, frac_decimals: u8) -> (u128, u128) {
+    assert!(
+        frac_decimals <= 19,
+        "at most 19 fractional digits are supported"
+    );
+    let denominator = 10u128.pow(u32::from(frac_decimals));
+    assert!(
+        u128::from(frac) < denominator,
+        "fraction has more digits than frac_decimals"
+    );
+    let numerator = u128::from(whole) * denominator + u128::from(frac);
+
+    let (mut a, mut b) = (numerator, denominator);
+    while b != 0 {
+        (a, b) = (b, a % b);
     }
+    (numerator / a, denominator / a)
+}
+
+/// The bytes of a `Mapping` entry's storage key: the mapping's `u32` root key
+/// followed by the encoded asset id.
+const MAPPING_KEY_BYTES: usize = 8;
+
+/// Returns the most storage bytes registering and configuring one pair adds: its
+/// `asset_pairs` and `pair_configs` entries, keys included, and its `asset_ids`
+/// element.
+pub fn estimated_pair_storage_bytes() -> u32 {
+    // Every `Option` set gives the largest encoding.
+    let config = psp22_ext::PairConfig {
+        max_swap: Some(0),
+        erc20_decimals_override: Some(0),
+        oracle: Some(DefaultAccountId::from([0; 32])),
+        max_oracle_age: Some(0),
+        ..Default::default()
+    };
+    let bytes = 2 * MAPPING_KEY_BYTES
+        + scale::Encode::encoded_size(&DefaultAccountId::from([0; 32]))
+        + scale::Encode::encoded_size(&config)
+        + scale::Encode::encoded_size(&psp22_ext::AssetId::MAX);
+    bytes as u32
+}
+
+/// A contract quoting live exchange rates for asset pairs.
+#[ink::trait_definition]
+pub trait PriceOracle {
+    /// Returns the current rate of the asset as `(numerator, denominator)`: the
+    /// amount of the asset paid out per `denominator` of ERC20 taken in.
+    #[ink(message)]
+    fn get_rate(&self, asset_id: u32) -> (u128, u128);
+
+    /// Returns the block timestamp, in milliseconds, at which the rate of the asset
+    /// was last updated.
+    #[ink(message)]
+    fn last_updated(&self, asset_id: u32) -> u64;
+}
+
+/// A contract notified by `swap_and_call` of the asset it was sent.
+#[ink::trait_definition]
+pub trait SwapReceiver {
+    /// Handles having received `amount` of the asset from a swap.
+    #[ink(message)]
+    fn on_swap_received(&mut self, asset_id: u32, amount: DefaultBalance);
+}
+
+/// Optional extensions of ERC20 contracts: wrapping the native currency and
+/// EIP-2612 style permits.
+#[ink::trait_definition]
+pub trait Erc20Trait {
+    /// Mints ERC20 to the caller for the native currency transferred with the call.
+    #[ink(message, payable)]
+    fn deposit(&mut self);
+
+    /// Returns the nonce the next permit signed by `owner` must carry.
+    #[ink(message)]
+    fn nonces(&self, owner: DefaultAccountId) -> u64;
 }
 
 /// An environment using default ink environment types, with PSP-22 extension included
@@ -112,164 +389,6294 @@ impl Environment for CustomEnvironment {
 
 #[ink::contract(env = crate::CustomEnvironment)]
 mod psp22_ext {
-    use ink::{prelude::vec::Vec, storage::Mapping};
+    use ink::{
+        prelude::vec::Vec,
+        storage::{Lazy, Mapping},
+    };
 
-    use super::Result;
+    use super::{Psp22Error, Result};
 
     pub type AssetId = u32;
+    /// A `(numerator, denominator)` reference rate and how far a rate may deviate
+    /// from it, in basis points of the reference rate.
+    type PriceBand = ((u128, u128), u16);
+    /// An allowance of an asset, as `(asset_id, owner, spender)`.
+    type AllowanceKey = (AssetId, AccountId, AccountId);
+    /// An idempotency key of a swap, as `(caller, key)`.
+    type RequestKey = (AccountId, [u8; 32]);
+    /// A swap into an asset, as the block timestamp it executed at and its
+    /// `(amount_out, amount_in)`.
+    type TwapPoint = (Timestamp, Balance, Balance);
     use erc20::Erc20Ref;
+
+    /// The denominator of fees expressed in basis points.
+    const BPS_DENOMINATOR: u128 = 10_000;
+
+    /// The number of receipts kept in each account's swap history.
+    const MAX_RECENT_SWAPS: usize = 10;
+
+    /// The most entries any batch message accepts at once.
+    const MAX_BATCH: usize = 50;
+
+    /// The most pairs a multi-hop swap may route through.
+    const MAX_HOPS: usize = 4;
+
+    /// The largest difference in decimals between an asset and its ERC20, as `10^38`
+    /// is the largest power of ten a `u128` holds.
+    const MAX_DECIMALS_GAP: u8 = 38;
+
+    /// The fixed-point scale of the execution rates averaged by `twap`.
+    const TWAP_PRECISION: u128 = 1_000_000_000_000;
+
+    /// How far back `twap` averages execution rates, in milliseconds.
+    const TWAP_WINDOW: Timestamp = 3_600_000;
+
+    /// The most swaps into an asset whose execution rates `twap` keeps.
+    const MAX_TWAP_POINTS: usize = 16;
+
+    /// The longest token name or symbol accepted from the runtime, in bytes.
+    const MAX_METADATA_LEN: usize = 128;
+
+    #[cfg(test)]
+    use tests::erc20_mock as erc20_calls;
+    #[cfg(test)]
+    use tests::oracle_mock as oracle_calls;
+    #[cfg(test)]
+    use tests::receiver_mock as receiver_calls;
+
+    /// Calls into the ERC20 contracts backing the asset pairs.
+    ///
+    /// The off-chain test environment cannot dispatch cross-contract calls, so unit
+    /// tests swap this module for an in-memory ledger.
+    #[cfg(not(test))]
+    mod erc20_calls {
+        use super::{AccountId, Balance, Erc20Ref};
+
+        pub fn transfer_from(
+            token: &mut Erc20Ref,
+            from: AccountId,
+            to: AccountId,
+            value: Balance,
+        ) -> Result<(), erc20::Error> {
+            token.transfer_from(from, to, value)
+        }
+
+        pub fn transfer(
+            token: &mut Erc20Ref,
+            to: AccountId,
+            value: Balance,
+        ) -> Result<(), erc20::Error> {
+            token.transfer(to, value)
+        }
+
+        pub fn allowance(token: &Erc20Ref, owner: AccountId, spender: AccountId) -> Balance {
+            token.allowance(owner, spender)
+        }
+
+        pub fn token_balance(token: &Erc20Ref, owner: AccountId) -> Balance {
+            token.balance_of(owner)
+        }
+
+        pub fn total_supply(token: &Erc20Ref) -> Balance {
+            token.total_supply()
+        }
+
+        /// Returns the decimals of `token`, or `None` if it does not implement
+        /// `decimals`.
+        pub fn decimals(token: &Erc20Ref) -> Option<u8> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            build_call::<ink::env::DefaultEnvironment>()
+                .call(ink::ToAccountId::to_account_id(token))
+                .exec_input(ExecutionInput::new(Selector::new(ink::selector_bytes!(
+                    "decimals"
+                ))))
+                .returns::<u8>()
+                .try_invoke()
+                .ok()?
+                .ok()
+        }
+
+        /// Wraps `value` of native currency into `token`, or returns `None` if `token`
+        /// does not implement `Erc20Trait::deposit`.
+        pub fn deposit(token: &Erc20Ref, value: Balance) -> Option<()> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            build_call::<ink::env::DefaultEnvironment>()
+                .call(ink::ToAccountId::to_account_id(token))
+                .transferred_value(value)
+                .exec_input(ExecutionInput::new(Selector::new(ink::selector_bytes!(
+                    "Erc20Trait::deposit"
+                ))))
+                .returns::<()>()
+                .try_invoke()
+                .ok()?
+                .ok()
+        }
+
+        /// Returns the permit nonce of `owner` in `token`, or `None` if `token` does not
+        /// implement `Erc20Trait::nonces`.
+        pub fn nonces(token: &Erc20Ref, owner: AccountId) -> Option<u64> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            build_call::<ink::env::DefaultEnvironment>()
+                .call(ink::ToAccountId::to_account_id(token))
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("Erc20Trait::nonces")))
+                        .push_arg(owner),
+                )
+                .returns::<u64>()
+                .try_invoke()
+                .ok()?
+                .ok()
+        }
+    }
+
+    /// Queries the `PriceOracle` contracts of the asset pairs.
+    #[cfg(not(test))]
+    mod oracle_calls {
+        use super::AccountId;
+
+        /// Returns the rate `oracle` quotes for the asset, or `None` if the call failed.
+        pub fn get_rate(oracle: AccountId, asset_id: u32) -> Option<(u128, u128)> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            build_call::<ink::env::DefaultEnvironment>()
+                .call(oracle)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!(
+                        "PriceOracle::get_rate"
+                    )))
+                    .push_arg(asset_id),
+                )
+                .returns::<(u128, u128)>()
+                .try_invoke()
+                .ok()?
+                .ok()
+        }
+
+        /// Returns when `oracle` last updated the rate of the asset, or `None` if the
+        /// call failed.
+        pub fn last_updated(oracle: AccountId, asset_id: u32) -> Option<u64> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            build_call::<ink::env::DefaultEnvironment>()
+                .call(oracle)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!(
+                        "PriceOracle::last_updated"
+                    )))
+                    .push_arg(asset_id),
+                )
+                .returns::<u64>()
+                .try_invoke()
+                .ok()?
+                .ok()
+        }
+    }
+
+    /// Notifies the `SwapReceiver` contracts swaps pay out to.
+    #[cfg(not(test))]
+    mod receiver_calls {
+        use super::{AccountId, Balance};
+
+        /// Calls `SwapReceiver::on_swap_received` on `receiver`, or returns `None` if
+        /// the call failed.
+        pub fn on_swap_received(receiver: AccountId, asset_id: u32, amount: Balance) -> Option<()> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            build_call::<ink::env::DefaultEnvironment>()
+                .call(receiver)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!(
+                        "SwapReceiver::on_swap_received"
+                    )))
+                    .push_arg(asset_id)
+                    .push_arg(amount),
+                )
+                .returns::<()>()
+                .try_invoke()
+                .ok()?
+                .ok()
+        }
+    }
+
+    /// Per-pair settings, stored together so a swap reads them in a single access.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct PairConfig {
+        /// Whether the asset wraps the native currency.
+        pub is_native_wrapper: bool,
+        /// The largest amount of ERC20 a single swap may take in, if capped.
+        pub max_swap: Option<Balance>,
+        /// Whether swaps above `max_swap` may be filled up to the cap.
+        pub allow_partial: bool,
+        /// Decimals to assume for a backing ERC20 that does not implement `decimals`.
+        pub erc20_decimals_override: Option<u8>,
+        /// Amount of the asset paid out per `rate_denominator` of ERC20 taken in.
+        pub rate_numerator: u128,
+        /// Amount of ERC20 that buys `rate_numerator` of the asset.
+        pub rate_denominator: u128,
+        /// Share of each swap's output retained as a fee, in basis points.
+        pub fee_bps: u16,
+        /// The least fee retained from each swap's output, capped at the output.
+        pub min_fee: Balance,
+        /// A `PriceOracle` whose live rate replaces the stored one when swapping.
+        pub oracle: Option<AccountId>,
+        /// The oldest an oracle rate may be, in seconds, if capped.
+        pub max_oracle_age: Option<u64>,
+        /// Whether the pair may be swapped at all.
+        pub enabled: bool,
+        /// Whether swaps of the pair are temporarily paused.
+        pub paused: bool,
+        /// Whether the rate applies to whole units rather than the smallest ones, so
+        /// that the decimals of the asset and the ERC20 are scaled for.
+        pub unit_mode: bool,
+        /// Bumped whenever the stored settings of the pair change; any value given
+        /// when setting them is ignored.
+        pub config_version: u32,
+    }
+
+    impl Default for PairConfig {
+        fn default() -> Self {
+            Self {
+                is_native_wrapper: false,
+                max_swap: None,
+                allow_partial: false,
+                erc20_decimals_override: None,
+                rate_numerator: 1,
+                rate_denominator: 1,
+                fee_bps: 0,
+                min_fee: 0,
+                oracle: None,
+                max_oracle_age: None,
+                enabled: true,
+                paused: false,
+                unit_mode: false,
+                config_version: 0,
+            }
+        }
+    }
+
+    impl PairConfig {
+        /// Returns the amount of the asset `amount` of ERC20 buys at the pair's rate,
+        /// rounded down.
+        fn output_for(&self, amount: Balance) -> Result<Balance> {
+            if self.rate_denominator == 0 {
+                return Err(Psp22Error::InvalidRate);
+            }
+            amount
+                .checked_mul(self.rate_numerator)
+                .map(|scaled| scaled / self.rate_denominator)
+                .ok_or(Psp22Error::Overflow)
+        }
+
+        /// Returns the amount of ERC20 `asset_amount` of the asset buys back at the
+        /// pair's rate, rounded down.
+        fn reverse_output_for(&self, asset_amount: Balance) -> Result<Balance> {
+            if self.rate_numerator == 0 {
+                return Err(Psp22Error::InvalidRate);
+            }
+            asset_amount
+                .checked_mul(self.rate_denominator)
+                .map(|scaled| scaled / self.rate_numerator)
+                .ok_or(Psp22Error::Overflow)
+        }
+
+        /// Returns the least amount of ERC20 that buys `output` of the asset after the
+        /// fee at the pair's rate.
+        fn input_for(&self, output: Balance) -> Result<Balance> {
+            if self.rate_numerator == 0 {
+                return Err(Psp22Error::InvalidRate);
+            }
+            let kept_bps = BPS_DENOMINATOR.saturating_sub(u128::from(self.fee_bps));
+            if kept_bps == 0 {
+                return Err(Psp22Error::ZeroOutput);
+            }
+            // The fee is rounded down, so `gross` pays out `ceil(gross * kept / BPS)`
+            // and the least sufficient gross is `floor((output - 1) * BPS / kept) + 1`.
+            // It must also cover `output` on top of the minimum fee.
+            let gross = match output.checked_sub(1) {
+                None => 0,
+                Some(rest) => {
+                    let by_share = rest
+                        .checked_mul(BPS_DENOMINATOR)
+                        .ok_or(Psp22Error::Overflow)?
+                        / kept_bps
+                        + 1;
+                    let by_floor = output
+                        .checked_add(self.min_fee)
+                        .ok_or(Psp22Error::Overflow)?;
+                    by_share.max(by_floor)
+                }
+            };
+            gross
+                .checked_mul(self.rate_denominator)
+                .map(|scaled| scaled.div_ceil(self.rate_numerator))
+                .ok_or(Psp22Error::Overflow)
+        }
+
+        /// Splits the output `amount` of ERC20 buys into the part paid out and the fee
+        /// retained.
+        fn output_and_fee(&self, amount: Balance) -> Result<(Balance, Balance)> {
+            let gross = self.output_for(amount)?;
+            let fee = gross
+                .checked_mul(Balance::from(self.fee_bps))
+                .ok_or(Psp22Error::Overflow)?
+                / BPS_DENOMINATOR;
+            let fee = fee.max(self.min_fee.min(gross));
+            Ok((gross - fee, fee))
+        }
+
+        /// Checks that the pair is enabled and not paused.
+        fn ensure_open(&self) -> Result<()> {
+            if !self.enabled {
+                return Err(Psp22Error::AssetDisabled);
+            }
+            if self.paused {
+                return Err(Psp22Error::AssetPaused);
+            }
+            Ok(())
+        }
+
+        /// Checks that the pair's rate lies within `max_deviation_bps` of the rate
+        /// `(reference_num, reference_den)`.
+        fn ensure_in_band(
+            &self,
+            (reference_num, reference_den): (u128, u128),
+            max_deviation_bps: u16,
+        ) -> Result<()> {
+            // Compares `|rate - reference| / reference` against the band with both
+            // sides scaled by `rate_denominator * reference_num * BPS_DENOMINATOR`.
+            let scaled = self
+                .rate_numerator
+                .checked_mul(reference_den)
+                .ok_or(Psp22Error::Overflow)?;
+            let reference_scaled = reference_num
+                .checked_mul(self.rate_denominator)
+                .ok_or(Psp22Error::Overflow)?;
+            let deviation = scaled
+                .abs_diff(reference_scaled)
+                .checked_mul(BPS_DENOMINATOR)
+                .ok_or(Psp22Error::Overflow)?;
+            let band = reference_scaled
+                .checked_mul(u128::from(max_deviation_bps))
+                .ok_or(Psp22Error::Overflow)?;
+            if deviation > band {
+                return Err(Psp22Error::PriceOutOfBand);
+            }
+            Ok(())
+        }
+
+        /// Checks that the settings are usable for swapping.
+        fn validate(&self) -> Result<()> {
+            if self.rate_numerator == 0 || self.rate_denominator == 0 {
+                return Err(Psp22Error::InvalidRate);
+            }
+            if u128::from(self.fee_bps) > BPS_DENOMINATOR {
+                return Err(Psp22Error::InvalidFee);
+            }
+            Ok(())
+        }
+    }
+
+    /// Configuration of a registered asset pair.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct PairInfo {
+        /// The ERC20 contract backing the asset.
+        pub erc20: AccountId,
+        /// The settings of the pair.
+        pub config: PairConfig,
+    }
+
+    /// The direction of a swap through a pair.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub enum SwapDirection {
+        /// ERC20 swapped for the asset.
+        ToAsset,
+        /// The asset swapped back for ERC20.
+        ToErc20,
+    }
+
+    /// Record of a completed swap.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct SwapReceipt {
+        /// The asset of the pair swapped through.
+        pub asset_id: AssetId,
+        /// Whether ERC20 was swapped for the asset or the other way around.
+        pub direction: SwapDirection,
+        /// The amount taken in.
+        pub amount_in: Balance,
+        /// The amount paid out.
+        pub amount_out: Balance,
+        /// The block timestamp of the swap.
+        pub timestamp: Timestamp,
+    }
+
+    /// Metadata of an asset, as reported by the runtime.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct TokenMetadata {
+        pub name: Vec<u8>,
+        pub symbol: Vec<u8>,
+        pub decimals: u8,
+    }
+
+    /// Event emitted when a non-reverting swap attempt fails.
+    #[ink(event)]
+    pub struct SwapFailed {
+        event_seq: u64,
+        #[ink(topic)]
+        caller: AccountId,
+        asset_id: AssetId,
+        direction: SwapDirection,
+        /// Discriminant of the `Psp22Error` the swap failed with.
+        reason: u8,
+    }
+
+    /// Event emitted whenever the ERC20 escrowed for an asset changes.
+    #[ink(event)]
+    pub struct EscrowChanged {
+        event_seq: u64,
+        #[ink(topic)]
+        asset_id: AssetId,
+        /// The direction of the swap that changed the escrow: ERC20 enters it on
+        /// `ToAsset` swaps and leaves it on `ToErc20` swaps.
+        direction: SwapDirection,
+        amount: Balance,
+        /// The escrowed amount after the change.
+        new_total: Balance,
+    }
+
+    /// Event emitted by `swap_for_asset_raw` in place of a receipt, carrying no topics.
+    #[ink(event)]
+    pub struct RawSwap {
+        event_seq: u64,
+        asset_id: AssetId,
+        /// The ERC20 this contract received.
+        amount_in: Balance,
+        amount_out: Balance,
+    }
+
+    /// Event emitted when the outflow of an asset trips the circuit breaker and
+    /// pauses swaps.
+    #[ink(event)]
+    pub struct CircuitBreakerTripped {
+        event_seq: u64,
+        #[ink(topic)]
+        asset_id: AssetId,
+        /// The outflow of the asset in the current window.
+        outflow: Balance,
+    }
+
+    /// Event emitted when the owner resets the escrow of an asset to the ERC20
+    /// actually held for it.
+    #[ink(event)]
+    pub struct EscrowSynced {
+        event_seq: u64,
+        #[ink(topic)]
+        asset_id: AssetId,
+        old: Balance,
+        new: Balance,
+    }
+
+    /// Event emitted when the owner changes the rate of a pair.
+    #[ink(event)]
+    pub struct RateChanged {
+        event_seq: u64,
+        #[ink(topic)]
+        asset_id: AssetId,
+        old_num: u128,
+        old_den: u128,
+        new_num: u128,
+        new_den: u128,
+    }
+
+    /// Event emitted when the owner clears the reentrancy lock with `force_unlock`.
+    #[ink(event)]
+    pub struct LockForceReset {
+        event_seq: u64,
+    }
+
+    /// Event emitted when the owner sends native currency out of the contract.
+    #[ink(event)]
+    pub struct NativeRescued {
+        event_seq: u64,
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
+    /// Event emitted when the asset is deposited to pay swaps out of.
+    #[ink(event)]
+    pub struct LiquidityDeposited {
+        event_seq: u64,
+        #[ink(topic)]
+        asset_id: AssetId,
+        amount: Balance,
+        #[ink(topic)]
+        provider: AccountId,
+    }
+
+    /// Event emitted when the owner withdraws liquidity of the asset.
+    #[ink(event)]
+    pub struct LiquidityWithdrawn {
+        event_seq: u64,
+        #[ink(topic)]
+        asset_id: AssetId,
+        amount: Balance,
+        #[ink(topic)]
+        to: AccountId,
+    }
+
     /// A chain extension which implements the PSP-22 fungible token standard.
     /// For more details see <https://github.com/w3f/PSPs/blob/master/PSPs/psp-22.md>
     #[ink(storage)]
-    #[derive(Default)]
     pub struct Psp22Extension {
         asset_pairs: Mapping<AssetId, Erc20Ref>,
-
+        /// Every asset id with a registered pair, in registration order.
+        asset_ids: Vec<AssetId>,
+        /// Settings of each registered pair.
+        pair_configs: Mapping<AssetId, PairConfig>,
+        /// The account allowed to configure the contract.
+        owner: AccountId,
+        /// Accounts allowed to swap while the allowlist is enabled.
+        allowlist: Mapping<AccountId, bool>,
+        /// Whether swaps are restricted to allowlisted accounts.
+        allowlist_enabled: bool,
+        /// Accounts banned from swapping.
+        blocklist: Mapping<AccountId, bool>,
+        /// The most recent swaps of each account, oldest first.
+        swap_history: Mapping<AccountId, Vec<SwapReceipt>>,
+        /// Settings given to pairs registered through `swap_or_create`.
+        pair_template: PairConfig,
+        /// Fees retained from swaps into each asset and not yet withdrawn.
+        accrued_fees: Mapping<AssetId, Balance>,
+        /// The account withdrawn fees are sent to.
+        fee_recipient: AccountId,
+        /// ERC20 held by this contract on behalf of each asset.
+        escrowed: Mapping<AssetId, Balance>,
+        /// Whether swaps are currently paused.
+        paused: bool,
+        /// The account allowed to pause and unpause swaps besides the owner.
+        pauser: AccountId,
+        /// The most of an asset swaps may pay out within one window before swaps are
+        /// paused, if capped.
+        max_outflow: Option<Balance>,
+        /// Length of an outflow window, in milliseconds.
+        outflow_window: Timestamp,
+        /// Start and running total of the current outflow window of each asset.
+        outflows: Mapping<AssetId, (Timestamp, Balance)>,
+        /// Idempotency keys of the swaps already processed, per caller.
+        processed_requests: Mapping<RequestKey, ()>,
+        /// Metadata of each asset stored by `cache_metadata`.
+        metadata_cache: Mapping<AssetId, TokenMetadata>,
+        /// Symbols of assets stored by `token_symbol_cached` alone.
+        symbol_cache: Mapping<AssetId, Vec<u8>>,
+        /// The shortest time an account must wait between swaps, in milliseconds.
+        swap_interval: Timestamp,
+        /// Bumped by `clear_rate_limits` to invalidate all recorded swap times.
+        rate_limit_epoch: u32,
+        /// The rate-limit epoch and block timestamp of each account's last swap.
+        last_swap_at: Mapping<AccountId, (u32, Timestamp)>,
+        /// Share of a swap's fee paid to its referrer, in basis points of the fee.
+        referral_bps: u16,
+        /// Fees paid out to each referrer.
+        referral_earnings: Mapping<AccountId, Balance>,
+        /// The proposed new owner and the block the proposal was made in.
+        pending_owner: Option<(AccountId, BlockNumber)>,
+        /// Blocks a proposed owner has to accept ownership in, if limited.
+        ownership_grace_period: Option<BlockNumber>,
+        /// The account sent a share of withdrawn fees alongside the fee recipient, and
+        /// that share in basis points, if fees are split.
+        fee_split: Option<(AccountId, u16)>,
+        /// The band the oracle rate of each banded pair must stay within.
+        price_bands: Mapping<AssetId, PriceBand>,
+        /// The ERC20 amount above which a swap of each cooled-down asset counts as
+        /// large, and the length of the cooldown after one, in blocks.
+        cooldowns: Mapping<AssetId, (Balance, BlockNumber)>,
+        /// The block of the last large swap of each cooled-down asset.
+        last_large_swap: Mapping<AssetId, BlockNumber>,
+        /// Whether the contract was permanently disabled.
+        terminated: bool,
+        /// Native currency `rescue_native` must leave in the contract.
+        native_reserve: Balance,
+        /// The asset paid out and ERC20 taken in by the last swap into each asset.
+        last_execution_rates: Mapping<AssetId, (u128, u128)>,
+        /// Asset ids from the first bound up to but excluding the second no pair may
+        /// be registered for.
+        reserved_range: (AssetId, AssetId),
+        /// The sequence number the next event carries, counting every event emitted.
+        event_seq: u64,
+        /// When each timed approval of this contract expires, keyed by the allowance
+        /// it times.
+        approval_expiries: Mapping<AllowanceKey, Timestamp>,
+        /// This contract's own account id, cached at construction for swaps to use.
+        contract_id: AccountId,
+        /// Whether `swap_and_call` is calling out to its recipient. Kept in its own
+        /// cell, which is written at once rather than when the message returns, so
+        /// that reentrant calls see it.
+        locked: Lazy<bool>,
+        /// The account swaps into the asset pay out to instead of the caller, if set.
+        default_recipient: Option<AccountId>,
+        /// The recent swaps into each asset `twap` averages over, oldest first.
+        twap_points: Mapping<AssetId, Vec<TwapPoint>>,
+        /// Whether an ERC20 may back at most one asset.
+        unique_backing: bool,
     }
 
     impl Psp22Extension {
         /// Creates a new instance of this contract.
         #[ink(constructor)]
         pub fn new() -> Self {
-            Self::default()
+            Self {
+                asset_pairs: Mapping::default(),
+                asset_ids: Vec::new(),
+                pair_configs: Mapping::default(),
+                owner: Self::env().caller(),
+                allowlist: Mapping::default(),
+                allowlist_enabled: false,
+                blocklist: Mapping::default(),
+                swap_history: Mapping::default(),
+                pair_template: PairConfig::default(),
+                accrued_fees: Mapping::default(),
+                fee_recipient: Self::env().caller(),
+                escrowed: Mapping::default(),
+                paused: false,
+                pauser: Self::env().caller(),
+                max_outflow: None,
+                outflow_window: 0,
+                outflows: Mapping::default(),
+                processed_requests: Mapping::default(),
+                metadata_cache: Mapping::default(),
+                symbol_cache: Mapping::default(),
+                swap_interval: 0,
+                rate_limit_epoch: 0,
+                last_swap_at: Mapping::default(),
+                referral_bps: 0,
+                referral_earnings: Mapping::default(),
+                pending_owner: None,
+                ownership_grace_period: None,
+                fee_split: None,
+                price_bands: Mapping::default(),
+                cooldowns: Mapping::default(),
+                last_large_swap: Mapping::default(),
+                terminated: false,
+                native_reserve: 0,
+                last_execution_rates: Mapping::default(),
+                reserved_range: (0, 0),
+                event_seq: 0,
+                approval_expiries: Mapping::default(),
+                contract_id: Self::env().account_id(),
+                locked: Lazy::default(),
+                default_recipient: None,
+                twap_points: Mapping::default(),
+                unique_backing: false,
+            }
         }
 
+        /// Creates a new instance of this contract with a pair for `asset_id` backed by
+        /// `erc20_address` already registered with `config`.
+        #[ink(constructor)]
+        pub fn new_with_pair(
+            erc20_address: AccountId,
+            asset_id: u32,
+            config: PairConfig,
+        ) -> Result<Self> {
+            config.validate()?;
+            let mut contract = Self::new();
+            let erc20: Erc20Ref = ink::env::call::FromAccountId::from_account_id(erc20_address);
+            contract.ensure_decimals_supported(asset_id, &erc20, config.erc20_decimals_override)?;
+            contract.register_pair(asset_id, &erc20);
+            contract.store_pair_config(asset_id, config);
+            Ok(contract)
+        }
+
+        /// Registers `erc20_address` as the ERC20 backing the asset. Only the owner may
+        /// register pairs.
+        ///
+        /// Fails with `DecimalsOutOfRange` if the decimals of the two are too far apart,
+        /// with `ReservedAssetId` if the asset id is reserved, with `Erc20AlreadyBound`
+        /// if backings must be unique and the ERC20 backs another asset, or with
+        /// `EscrowNotEmpty` if the pair is already backed by another ERC20 that the
+        /// contract still escrows.
         #[ink(message)]
-        pub fn create_asset_pair(&mut self, asset_id: u32, erc20_address: Erc20Ref) {
-            // Map `asset_id` to the ERC20 contract for this pair
-            self.asset_pairs
-                .insert::<AssetId, Erc20Ref>(asset_id, &erc20_address);
+        pub fn create_asset_pair(&mut self, asset_id: u32, erc20_address: Erc20Ref) -> Result<()> {
+            self.ensure_owner()?;
+            if let Some(current) = self.asset_pairs.get(asset_id) {
+                let rebinding = ink::ToAccountId::to_account_id(&current)
+                    != ink::ToAccountId::to_account_id(&erc20_address);
+                if rebinding && self.escrowed_of(asset_id) > 0 {
+                    return Err(Psp22Error::EscrowNotEmpty);
+                }
+            }
+            self.ensure_not_reserved(asset_id)?;
+            self.ensure_backing_unbound(asset_id, &erc20_address)?;
+            let decimals_override = self.pair_config_of(asset_id).erc20_decimals_override;
+            self.ensure_decimals_supported(asset_id, &erc20_address, decimals_override)?;
+            self.register_pair(asset_id, &erc20_address);
+            Ok(())
         }
 
+        /// Unregisters the pair of the asset along with its settings.
+        ///
+        /// Fails with `EscrowNotEmpty` while the contract escrows ERC20 for the asset,
+        /// which has to be swapped back out first.
         #[ink(message)]
-        pub fn swap_for_asset(&mut self, asset_id: u32, amount: Balance) {
-            let mut erc20 = self.asset_pairs.get(asset_id).expect("Asset pair not found!");
+        pub fn remove_asset_pair(&mut self, asset_id: u32) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            if self.escrowed_of(asset_id) > 0 {
+                return Err(Psp22Error::EscrowNotEmpty);
+            }
+            self.remove_pair(asset_id);
+            Ok(())
+        }
 
-            // contract needs to be approved to spend funds
-            let erc20_result =
-                erc20.transfer_from(self.env().caller(), self.env().account_id(), amount);
+        /// Unregisters the pairs of several assets, skipping assets without one.
+        ///
+        /// Fails with `EscrowNotEmpty`, removing none, if the contract escrows ERC20
+        /// for any of them. Returns the number of pairs removed.
+        #[ink(message)]
+        pub fn remove_asset_pairs(&mut self, asset_ids: Vec<u32>) -> Result<u32> {
+            self.ensure_owner()?;
+            Self::ensure_batch_size(asset_ids.len())?;
+            if asset_ids
+                .iter()
+                .any(|&asset_id| self.escrowed_of(asset_id) > 0)
+            {
+                return Err(Psp22Error::EscrowNotEmpty);
+            }
+            let mut removed = 0;
+            for asset_id in asset_ids {
+                if self.asset_pairs.contains(asset_id) {
+                    self.remove_pair(asset_id);
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        }
 
-            assert!(erc20_result.is_ok(), "erc20_result {:?}", erc20_result);
+        /// Reserves the asset ids from `start` up to but excluding `end` for system
+        /// assets, so no pair can be registered for them. An empty range disables it.
+        #[ink(message)]
+        pub fn set_reserved_range(&mut self, start: u32, end: u32) -> Result<()> {
+            self.ensure_owner()?;
+            self.reserved_range = (start, end);
+            Ok(())
+        }
 
-            let ext_result = self
-                .env()
-                .extension()
-                .transfer(asset_id, self.env().caller(), amount);
+        /// Sets whether an ERC20 may back at most one asset. Pairs registered before
+        /// are left as they are.
+        #[ink(message)]
+        pub fn set_unique_backing(&mut self, unique_backing: bool) -> Result<()> {
+            self.ensure_owner()?;
+            self.unique_backing = unique_backing;
+            Ok(())
+        }
 
-            assert!(ext_result.is_ok(), "ext_result {:?}", ext_result);
+        /// Returns the range of asset ids reserved for system assets.
+        #[ink(message)]
+        pub fn reserved_range(&self) -> (u32, u32) {
+            self.reserved_range
         }
 
-        // PSP22 Metadata interfaces
+        /// Sets the settings given to pairs registered through `swap_or_create`.
+        #[ink(message)]
+        pub fn set_pair_template(&mut self, config: PairConfig) -> Result<()> {
+            self.ensure_owner()?;
+            config.validate()?;
+            self.pair_template = config;
+            Ok(())
+        }
 
-        /// Returns the token name of the specified asset.
-        #[ink(message, selector = 0x3d261bd4)]
-        pub fn token_name(&self, asset_id: u32) -> Result<Vec<u8>> {
-            self.env().extension().token_name(asset_id)
+        /// Returns the configuration of the asset's pair, or `None` if no pair is
+        /// registered for it.
+        #[ink(message)]
+        pub fn pair_config(&self, asset_id: u32) -> Option<PairConfig> {
+            self.asset_pairs
+                .contains(asset_id)
+                .then(|| self.pair_config_of(asset_id))
         }
 
-        /// Returns the token symbol of the specified asset.
-        #[ink(message, selector = 0x34205be5)]
-        pub fn token_symbol(&self, asset_id: u32) -> Result<Vec<u8>> {
-            self.env().extension().token_symbol(asset_id)
+        /// Returns the config version of the asset's pair, or `None` if no pair is
+        /// registered for it. Clients can compare versions to tell whether the pair's
+        /// settings changed.
+        #[ink(message)]
+        pub fn pair_config_version(&self, asset_id: u32) -> Option<u32> {
+            self.pair_config(asset_id)
+                .map(|config| config.config_version)
         }
 
-        /// Returns the token decimals of the specified asset.
-        #[ink(message, selector = 0x7271b782)]
-        pub fn token_decimals(&self, asset_id: u32) -> Result<u8> {
-            self.env().extension().token_decimals(asset_id)
+        /// Returns the stored rate of the asset's pair inverted, as the amount of ERC20
+        /// paid per `denominator` of the asset, for displaying both directions.
+        ///
+        /// Returns `None` if no pair is registered or either part of the rate is zero.
+        #[ink(message)]
+        pub fn inverse_rate(&self, asset_id: u32) -> Option<(u128, u128)> {
+            let config = self.pair_config(asset_id)?;
+            (config.rate_numerator != 0 && config.rate_denominator != 0)
+                .then_some((config.rate_denominator, config.rate_numerator))
         }
 
-        // PSP22 interface queries
+        /// Returns every registered asset pair along with its configuration.
+        #[ink(message)]
+        pub fn all_pairs(&self) -> Vec<(AssetId, PairInfo)> {
+            self.asset_ids
+                .iter()
+                .filter_map(|&asset_id| {
+                    let erc20 = self.asset_pairs.get(asset_id)?;
+                    Some((
+                        asset_id,
+                        PairInfo {
+                            erc20: ink::ToAccountId::to_account_id(&erc20),
+                            config: self.pair_config_of(asset_id),
+                        },
+                    ))
+                })
+                .collect()
+        }
 
-        /// Returns the total token supply of the specified asset.
-        #[ink(message, selector = 0x162df8c2)]
-        pub fn total_supply(&self, asset_id: u32) -> Result<Balance> {
-            self.env().extension().total_supply(asset_id)
+        /// Flags whether the asset of a pair wraps the native currency.
+        #[ink(message)]
+        pub fn set_native_wrapper(&mut self, asset_id: u32, is_native_wrapper: bool) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let mut config = self.pair_config_of(asset_id);
+            config.is_native_wrapper = is_native_wrapper;
+            self.store_pair_config(asset_id, config);
+            Ok(())
         }
 
-        /// Returns the account balance for the specified asset & owner.
-        #[ink(message, selector = 0x6568382f)]
-        pub fn balance_of(&self, asset_id: u32, owner: AccountId) -> Result<Balance> {
-            self.env().extension().balance_of(asset_id, owner)
+        /// Adds `account` to or removes it from the swap allowlist.
+        #[ink(message)]
+        pub fn set_allowlisted(&mut self, account: AccountId, allowed: bool) -> Result<()> {
+            self.ensure_owner()?;
+            self.allowlist.insert(account, &allowed);
+            Ok(())
         }
 
-        /// Returns the amount which `spender` is still allowed to withdraw from `owner`
-        /// for the specified asset.
-        #[ink(message, selector = 0x4d47d921)]
-        pub fn allowance(
-            &self,
-            asset_id: u32,
-            owner: AccountId,
-            spender: AccountId,
-        ) -> Result<Balance> {
-            self.env().extension().allowance(asset_id, owner, spender)
+        /// Restricts swaps to allowlisted accounts while `enabled` is set.
+        #[ink(message)]
+        pub fn set_allowlist_enabled(&mut self, enabled: bool) -> Result<()> {
+            self.ensure_owner()?;
+            self.allowlist_enabled = enabled;
+            Ok(())
         }
 
-        // PSP22 transfer
+        /// Adds `account` to or removes it from the swap blocklist.
+        #[ink(message)]
+        pub fn set_blocked(&mut self, account: AccountId, blocked: bool) -> Result<()> {
+            self.ensure_owner()?;
+            self.blocklist.insert(account, &blocked);
+            Ok(())
+        }
 
-        /// Transfers `value` amount of specified asset from the caller's account to the
-        /// account `to`.
-        #[ink(message, selector = 0xdb20f9f5)]
-        pub fn transfer(&mut self, asset_id: u32, to: AccountId, value: Balance) -> Result<()> {
-            self.env().extension().transfer(asset_id, to, value)
+        /// Replaces the settings of a pair.
+        #[ink(message)]
+        pub fn set_pair_config(&mut self, asset_id: u32, config: PairConfig) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            config.validate()?;
+            self.store_pair_config(asset_id, config);
+            Ok(())
         }
 
-        // PSP22 transfer_from
+        /// Returns the decimals of the ERC20 backing a pair.
+        ///
+        /// Falls back to the pair's `erc20_decimals_override` for ERC20s that do not
+        /// implement `decimals`.
+        #[ink(message)]
+        pub fn erc20_decimals(&self, asset_id: u32) -> Result<u8> {
+            let erc20 = self
+                .asset_pairs
+                .get(asset_id)
+                .ok_or(Psp22Error::AssetPairNotFound)?;
+            erc20_calls::decimals(&erc20)
+                .or(self.pair_config_of(asset_id).erc20_decimals_override)
+                .ok_or(Psp22Error::Erc20DecimalsUnknown)
+        }
 
-        /// Transfers `value` amount of specified asset on the behalf of `from` to the
-        /// account `to`.
-        #[ink(message, selector = 0x54b3c76e)]
-        pub fn transfer_from(
+        /// Returns the nonce the next permit `owner` signs for the ERC20 backing a pair
+        /// must carry.
+        #[ink(message)]
+        pub fn permit_nonce(&self, asset_id: u32, owner: AccountId) -> Result<u64> {
+            let erc20 = self
+                .asset_pairs
+                .get(asset_id)
+                .ok_or(Psp22Error::AssetPairNotFound)?;
+            erc20_calls::nonces(&erc20, owner).ok_or(Psp22Error::PermitUnsupported)
+        }
+
+        /// Returns what `amount_in` of the first hop's ERC20 would finally buy if each
+        /// later hop sold the previous hop's output back for the ERC20 and swapped that
+        /// for its asset, after each pair's fee.
+        ///
+        /// Fails with `InvalidRoute` if there are no hops or more than `MAX_HOPS`, or
+        /// unless the same ERC20 backs every hop's pair.
+        #[ink(message)]
+        pub fn quote_multi_hop(&self, hops: Vec<u32>, amount_in: Balance) -> Result<Balance> {
+            if hops.is_empty() || hops.len() > MAX_HOPS {
+                return Err(Psp22Error::InvalidRoute);
+            }
+            self.ensure_shared_backing(&hops)?;
+            let mut previous: Option<PairConfig> = None;
+            hops.into_iter().try_fold(amount_in, |amount, asset_id| {
+                let config = self.live_config_of(asset_id)?;
+                let spent = match &previous {
+                    Some(previous) => previous.reverse_output_for(amount)?,
+                    None => amount,
+                };
+                let (output, _) = config.output_and_fee(spent)?;
+                previous = Some(config);
+                Ok(output)
+            })
+        }
+
+        /// Swaps `amount_in` of the first hop's ERC20 for its asset, then sells each
+        /// hop's output back to its pair for the ERC20 and swaps that for the next
+        /// hop's asset, paying the last hop's output to the caller.
+        ///
+        /// The same ERC20 must back every hop's pair, so each hop is paid for out of
+        /// the escrow the previous one released and intermediate assets never leave
+        /// this contract. Every hop is checked as a swap of its own before any funds
+        /// move, and the swap fails with `SlippageExceeded` if it would pay out less
+        /// than `min_out`.
+        #[ink(message)]
+        pub fn swap_multi_hop(
             &mut self,
-            asset_id: u32,
-            from: AccountId,
-            to: AccountId,
-            value: Balance,
-        ) -> Result<()> {
-            self.env()
-                .extension()
-                .transfer_from(asset_id, from, to, value)
+            hops: Vec<u32>,
+            amount_in: Balance,
+            min_out: Balance,
+        ) -> Result<Balance> {
+            if hops.is_empty() || hops.len() > MAX_HOPS {
+                return Err(Psp22Error::InvalidRoute);
+            }
+            self.ensure_shared_backing(&hops)?;
+            let mut configs: Vec<PairConfig> = Vec::with_capacity(hops.len());
+            let mut amount = amount_in;
+            for &asset_id in &hops {
+                let config = self.live_config_of(asset_id)?;
+                let spent = match configs.last() {
+                    Some(previous) => Self::sell_back(previous, amount)?,
+                    None => amount,
+                };
+                let (_, output, _) = self.quote_swap(asset_id, &config, spent)?;
+                configs.push(config);
+                amount = output;
+            }
+            Self::ensure_min_out(amount, min_out)?;
+
+            let (received, mut output, fee) = self.pull_erc20(hops[0], &configs[0], amount_in)?;
+            self.change_escrow(hops[0], SwapDirection::ToAsset, received);
+            self.record_hop(hops[0], received, fee);
+            self.record_rate(hops[0], received, output);
+            self.push_receipt(SwapDirection::ToAsset, hops[0], received, output);
+            for index in 1..hops.len() {
+                let (previous, asset_id) = (hops[index - 1], hops[index]);
+                // Selling an output back never releases more than the hop escrowed.
+                let spent = Self::sell_back(&configs[index - 1], output)?;
+                self.change_escrow(previous, SwapDirection::ToErc20, spent);
+                self.push_receipt(SwapDirection::ToErc20, previous, output, spent);
+                let (hop_output, fee) = configs[index].output_and_fee(spent)?;
+                self.change_escrow(asset_id, SwapDirection::ToAsset, spent);
+                self.record_hop(asset_id, spent, fee);
+                self.record_rate(asset_id, spent, hop_output);
+                self.push_receipt(SwapDirection::ToAsset, asset_id, spent, hop_output);
+                output = hop_output;
+            }
+            // Tokens charging a fee on transfer may shrink the quoted output.
+            Self::ensure_min_out(output, min_out)?;
+            if output == 0 {
+                return Err(Psp22Error::ZeroOutput);
+            }
+            let last = hops[hops.len() - 1];
+            self.pay_out(last, output)?;
+            self.track_outflow(last, output);
+            self.note_swap_time();
+            Ok(output)
         }
 
-        // PSP22 approve
+        /// Fails with `InvalidRoute` unless the same ERC20 backs the pair of every hop,
+        /// or with `AssetPairNotFound` if a hop has no pair.
+        fn ensure_shared_backing(&self, hops: &[AssetId]) -> Result<()> {
+            let mut backing = None;
+            for &asset_id in hops {
+                let erc20 = self
+                    .asset_pairs
+                    .get(asset_id)
+                    .ok_or(Psp22Error::AssetPairNotFound)?;
+                let token = ink::ToAccountId::to_account_id(&erc20);
+                if backing.is_some_and(|backing| backing != token) {
+                    return Err(Psp22Error::InvalidRoute);
+                }
+                backing = Some(token);
+            }
+            Ok(())
+        }
 
-        /// Allows `spender` to withdraw from the caller's account multiple times, up to
-        /// the `value` amount of the specified asset.
-        #[ink(message, selector = 0xb20f1bbd)]
-        pub fn approve(&mut self, asset_id: u32, spender: AccountId, value: Balance) -> Result<()> {
-            self.env().extension().approve(asset_id, spender, value)
+        /// Returns the ERC20 that selling `asset_amount` of the asset back to its pair
+        /// releases, failing with `ZeroOutput` if that rounds down to nothing.
+        fn sell_back(config: &PairConfig, asset_amount: Balance) -> Result<Balance> {
+            let output = config.reverse_output_for(asset_amount)?;
+            if output == 0 {
+                return Err(Psp22Error::ZeroOutput);
+            }
+            Ok(output)
         }
 
-        // PSP22 increase_allowance
+        /// Returns whether swapping `amount` of the pair's ERC20 would pay out nothing
+        /// once the output is rounded down.
+        #[ink(message)]
+        pub fn would_round_to_zero(&self, asset_id: u32, amount: Balance) -> Result<bool> {
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let (output, _) = self.live_config_of(asset_id)?.output_and_fee(amount)?;
+            Ok(output == 0)
+        }
 
-        /// Atomically increases the allowance for the specified asset granted to
-        /// `spender` by the caller.
-        #[ink(message, selector = 0x96d6b57a)]
-        pub fn increase_allowance(
+        /// Sets the share of a pair's swap output retained as a fee, in basis points.
+        #[ink(message)]
+        pub fn set_fee(&mut self, asset_id: u32, fee_bps: u16) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let config = PairConfig {
+                fee_bps,
+                ..self.pair_config_of(asset_id)
+            };
+            config.validate()?;
+            self.store_pair_config(asset_id, config);
+            Ok(())
+        }
+
+        /// Sets the least fee retained from each swap of the pair, applied when the
+        /// share set by `set_fee` rounds lower.
+        #[ink(message)]
+        pub fn set_min_fee(&mut self, asset_id: u32, min_fee: Balance) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let config = PairConfig {
+                min_fee,
+                ..self.pair_config_of(asset_id)
+            };
+            self.store_pair_config(asset_id, config);
+            Ok(())
+        }
+
+        /// Sets whether the pair's rate applies to whole units of the asset and the
+        /// ERC20 rather than their smallest units.
+        #[ink(message)]
+        pub fn set_unit_mode(&mut self, asset_id: u32, unit_mode: bool) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let config = PairConfig {
+                unit_mode,
+                ..self.pair_config_of(asset_id)
+            };
+            self.store_pair_config(asset_id, config);
+            Ok(())
+        }
+
+        /// Sets the rate of a pair to `num` of the asset per `den` of ERC20.
+        #[ink(message)]
+        pub fn set_rate(&mut self, asset_id: u32, num: u128, den: u128) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let old = self.pair_config_of(asset_id);
+            let config = PairConfig {
+                rate_numerator: num,
+                rate_denominator: den,
+                ..old.clone()
+            };
+            config.validate()?;
+            self.store_pair_config(asset_id, config);
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(RateChanged {
+                event_seq,
+                asset_id,
+                old_num: old.rate_numerator,
+                old_den: old.rate_denominator,
+                new_num: num,
+                new_den: den,
+            });
+            Ok(())
+        }
+
+        /// Sets the `(numerator, denominator)` rate the oracle rate of a pair is checked
+        /// against, allowing swaps only while the oracle rate is within
+        /// `max_deviation_bps` of it. `None` removes the band.
+        #[ink(message)]
+        pub fn set_reference_rate(
             &mut self,
             asset_id: u32,
-            spender: AccountId,
-            value: Balance,
+            reference_rate: Option<(u128, u128)>,
+            max_deviation_bps: u16,
         ) -> Result<()> {
-            self.env()
-                .extension()
-                .increase_allowance(asset_id, spender, value)
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let Some((num, den)) = reference_rate else {
+                self.price_bands.remove(asset_id);
+                return Ok(());
+            };
+            if num == 0 || den == 0 {
+                return Err(Psp22Error::InvalidRate);
+            }
+            self.price_bands
+                .insert(asset_id, &((num, den), max_deviation_bps));
+            Ok(())
         }
 
-        // PSP22 decrease_allowance
+        /// Sets the account withdrawn fees are sent to.
+        #[ink(message)]
+        pub fn set_fee_recipient(&mut self, fee_recipient: AccountId) -> Result<()> {
+            self.ensure_owner()?;
+            self.fee_recipient = fee_recipient;
+            Ok(())
+        }
 
-        /// Atomically decreases the allowance for the specified asset granted to
-        /// `spender` by the caller.
-        #[ink(message, selector = 0xfecb57d5)]
-        pub fn decrease_allowance(
+        /// Splits withdrawn fees between `treasury`, which becomes the fee recipient, and
+        /// `buyback`, giving each the given share in basis points.
+        ///
+        /// The shares must add up to `BPS_DENOMINATOR`; any rounding dust goes to the
+        /// treasury.
+        #[ink(message)]
+        pub fn set_fee_split(
             &mut self,
-            asset_id: u32,
-            spender: AccountId,
-            value: Balance,
+            treasury: AccountId,
+            treasury_bps: u16,
+            buyback: AccountId,
+            buyback_bps: u16,
         ) -> Result<()> {
-            self.env()
-                .extension()
-                .decrease_allowance(asset_id, spender, value)
+            self.ensure_owner()?;
+            if u128::from(treasury_bps) + u128::from(buyback_bps) != BPS_DENOMINATOR {
+                return Err(Psp22Error::InvalidSplit);
+            }
+            self.fee_recipient = treasury;
+            self.fee_split = (buyback_bps > 0).then_some((buyback, buyback_bps));
+            Ok(())
+        }
+
+        /// Proposes `new_owner` as the owner of the contract. Ownership only changes
+        /// once they call `accept_ownership`.
+        #[ink(message)]
+        pub fn propose_owner(&mut self, new_owner: AccountId) -> Result<()> {
+            self.ensure_owner()?;
+            self.pending_owner = Some((new_owner, self.env().block_number()));
+            Ok(())
+        }
+
+        /// Transfers ownership of the contract to `new_owner`, either at once or through
+        /// `propose_owner` and `accept_ownership` if `require_two_step` is set.
+        ///
+        /// The accept step proves `new_owner` can sign for the contract, so a mistyped
+        /// address cannot lock the owner out. A multisig may already guard against that
+        /// in its own governance and would otherwise need a second round of signatures,
+        /// which the immediate path saves. The immediate path also drops any pending
+        /// proposal.
+        #[ink(message)]
+        pub fn transfer_ownership(
+            &mut self,
+            new_owner: AccountId,
+            require_two_step: bool,
+        ) -> Result<()> {
+            if require_two_step {
+                return self.propose_owner(new_owner);
+            }
+            self.ensure_owner()?;
+            self.owner = new_owner;
+            self.pending_owner = None;
+            Ok(())
+        }
+
+        /// Makes the caller the owner, if they were proposed within the grace period.
+        #[ink(message)]
+        pub fn accept_ownership(&mut self) -> Result<()> {
+            self.ensure_live()?;
+            let caller = self.env().caller();
+            let Some((pending_owner, proposed_at)) = self.pending_owner else {
+                return Err(Psp22Error::NotPendingOwner);
+            };
+            if caller != pending_owner {
+                return Err(Psp22Error::NotPendingOwner);
+            }
+            if let Some(grace_period) = self.ownership_grace_period {
+                if self.env().block_number() > proposed_at.saturating_add(grace_period) {
+                    return Err(Psp22Error::ProposalExpired);
+                }
+            }
+            self.owner = caller;
+            self.pending_owner = None;
+            Ok(())
+        }
+
+        /// Sets the number of blocks a proposed owner has to accept ownership in.
+        /// Passing `None` lets proposals stand until accepted or replaced.
+        #[ink(message)]
+        pub fn set_ownership_grace_period(&mut self, blocks: Option<BlockNumber>) -> Result<()> {
+            self.ensure_owner()?;
+            self.ownership_grace_period = blocks;
+            Ok(())
+        }
+
+        /// Returns whether `account` owns the contract.
+        #[ink(message)]
+        pub fn is_owner(&self, account: AccountId) -> bool {
+            account == self.owner
+        }
+
+        /// Returns the owner, the pauser and the fee recipient, in that order.
+        #[ink(message)]
+        pub fn roles(&self) -> (AccountId, AccountId, AccountId) {
+            (self.owner, self.pauser, self.fee_recipient)
+        }
+
+        /// Sets the account allowed to pause and unpause swaps.
+        #[ink(message)]
+        pub fn set_pauser(&mut self, pauser: AccountId) -> Result<()> {
+            self.ensure_owner()?;
+            self.pauser = pauser;
+            Ok(())
+        }
+
+        /// Pauses or unpauses all swaps. Callable by the owner or the pauser.
+        #[ink(message)]
+        pub fn set_paused(&mut self, paused: bool) -> Result<()> {
+            self.ensure_pauser()?;
+            self.paused = paused;
+            Ok(())
+        }
+
+        /// Pauses or unpauses swaps of a single asset. Callable by the owner or the
+        /// pauser.
+        #[ink(message)]
+        pub fn set_asset_paused(&mut self, asset_id: u32, paused: bool) -> Result<()> {
+            self.ensure_pauser()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let mut config = self.pair_config_of(asset_id);
+            config.paused = paused;
+            self.store_pair_config(asset_id, config);
+            Ok(())
+        }
+
+        /// Enables or disables swaps of a single asset.
+        #[ink(message)]
+        pub fn set_asset_enabled(&mut self, asset_id: u32, enabled: bool) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let mut config = self.pair_config_of(asset_id);
+            config.enabled = enabled;
+            self.store_pair_config(asset_id, config);
+            Ok(())
+        }
+
+        /// Sets the shortest time an account must wait between swaps, in milliseconds.
+        /// Zero disables rate limiting.
+        #[ink(message)]
+        pub fn set_swap_interval(&mut self, interval: Timestamp) -> Result<()> {
+            self.ensure_owner()?;
+            self.swap_interval = interval;
+            Ok(())
+        }
+
+        /// Forgets when every account last swapped, lifting all current rate limits.
+        #[ink(message)]
+        pub fn clear_rate_limits(&mut self) -> Result<()> {
+            self.ensure_owner()?;
+            self.rate_limit_epoch = self.rate_limit_epoch.wrapping_add(1);
+            Ok(())
+        }
+
+        /// Pauses swaps once more than `max_outflow` of an asset is paid out within
+        /// `window` milliseconds. Passing `None` disables the circuit breaker.
+        ///
+        /// A tripped circuit breaker stays paused until unpaused with `set_paused`.
+        #[ink(message)]
+        pub fn set_circuit_breaker(
+            &mut self,
+            max_outflow: Option<Balance>,
+            window: Timestamp,
+        ) -> Result<()> {
+            self.ensure_owner()?;
+            self.max_outflow = max_outflow;
+            self.outflow_window = window;
+            Ok(())
+        }
+
+        /// Blocks swaps of more than `threshold` ERC20 into the asset for `blocks`
+        /// blocks after such a swap. Passing `None` removes the cooldown.
+        #[ink(message)]
+        pub fn set_cooldown(
+            &mut self,
+            asset_id: u32,
+            cooldown: Option<(Balance, BlockNumber)>,
+        ) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            match cooldown {
+                Some(cooldown) => {
+                    self.cooldowns.insert(asset_id, &cooldown);
+                }
+                None => {
+                    self.cooldowns.remove(asset_id);
+                    self.last_large_swap.remove(asset_id);
+                }
+            }
+            Ok(())
+        }
+
+        /// Returns whether swaps are currently paused.
+        #[ink(message)]
+        pub fn is_paused(&self) -> bool {
+            self.paused
+        }
+
+        /// Sets the share of each swap's fee paid to its referrer, in basis points of
+        /// the fee.
+        #[ink(message)]
+        pub fn set_referral_cut(&mut self, referral_bps: u16) -> Result<()> {
+            self.ensure_owner()?;
+            if u128::from(referral_bps) > BPS_DENOMINATOR {
+                return Err(Psp22Error::InvalidFee);
+            }
+            self.referral_bps = referral_bps;
+            Ok(())
+        }
+
+        /// Returns the fees paid out to `referrer`.
+        #[ink(message)]
+        pub fn referral_earnings_of(&self, referrer: AccountId) -> Balance {
+            self.referral_earnings.get(referrer).unwrap_or_default()
+        }
+
+        /// Returns the fees retained from swaps into the asset and not yet withdrawn.
+        #[ink(message)]
+        pub fn accrued_fees_of(&self, asset_id: u32) -> Balance {
+            self.accrued_fees.get(asset_id).unwrap_or_default()
+        }
+
+        /// Returns the fees accrued across all registered assets and not yet withdrawn.
+        #[ink(message)]
+        pub fn total_accrued_fees(&self) -> Balance {
+            self.asset_ids
+                .iter()
+                .map(|&asset_id| self.accrued_fees_of(asset_id))
+                .fold(0, Balance::saturating_add)
+        }
+
+        /// Returns the rate the last swap into the asset executed at, after fees, as the
+        /// `(numerator, denominator)` pair of asset paid out and ERC20 taken in.
+        #[ink(message)]
+        pub fn last_execution_rate(&self, asset_id: u32) -> Option<(u128, u128)> {
+            self.last_execution_rates.get(asset_id)
+        }
+
+        /// Returns the average rate swaps into the asset executed at over the last
+        /// `TWAP_WINDOW`, each weighted by how long it stood before the next swap or
+        /// until now, as a `(numerator, denominator)` pair like `last_execution_rate`.
+        ///
+        /// Only the last `MAX_TWAP_POINTS` swaps are kept, so a busy asset is averaged
+        /// over a shorter span. Rates and sums too large for a `u128` are clamped to
+        /// `u128::MAX`. Returns `None` if the asset was never swapped into.
+        #[ink(message)]
+        pub fn twap(&self, asset_id: u32) -> Option<(u128, u128)> {
+            let points = self.twap_points.get(asset_id).unwrap_or_default();
+            let &(_, last_out, last_in) = points.last()?;
+            let rate_of = |amount_out: Balance, amount_in: Balance| {
+                amount_out.saturating_mul(TWAP_PRECISION) / amount_in
+            };
+            let now = self.env().block_timestamp();
+            let window_start = now.saturating_sub(TWAP_WINDOW);
+            let (mut weighted_rates, mut elapsed) = (0u128, 0u128);
+            for (index, &(at, amount_out, amount_in)) in points.iter().enumerate() {
+                let end = points.get(index + 1).map_or(now, |&(next, _, _)| next);
+                let span = u128::from(end.saturating_sub(at.max(window_start)));
+                weighted_rates = weighted_rates
+                    .saturating_add(rate_of(amount_out, amount_in).saturating_mul(span));
+                elapsed += span;
+            }
+            if elapsed == 0 {
+                return Some((rate_of(last_out, last_in), TWAP_PRECISION));
+            }
+            Some((weighted_rates, TWAP_PRECISION.saturating_mul(elapsed)))
+        }
+
+        /// Returns the ERC20 held by this contract on behalf of the asset.
+        #[ink(message)]
+        pub fn escrowed_of(&self, asset_id: u32) -> Balance {
+            self.escrowed.get(asset_id).unwrap_or_default()
+        }
+
+        /// Returns the selector of each PSP22 message alongside the id of the extension
+        /// method it forwards to, for routers dispatching on the operation.
+        #[ink(message)]
+        pub fn selector_map(&self) -> Vec<(Vec<u8>, u32)> {
+            crate::extension_ids::SELECTORS
+                .iter()
+                .map(|(selector, func_id)| (selector.to_vec(), *func_id))
+                .collect()
+        }
+
+        /// Returns the total supply of the ERC20 backing the asset.
+        #[ink(message)]
+        pub fn backing_total_supply(&self, asset_id: u32) -> Result<Balance> {
+            let erc20 = self
+                .asset_pairs
+                .get(asset_id)
+                .ok_or(Psp22Error::AssetPairNotFound)?;
+            Ok(erc20_calls::total_supply(&erc20))
+        }
+
+        /// Returns the ERC20 escrowed for the asset alongside the asset's total supply,
+        /// from which clients can tell whether the asset is fully backed.
+        #[ink(message)]
+        pub fn collateral_ratio(&self, asset_id: u32) -> Result<(Balance, Balance)> {
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            Ok((self.escrowed_of(asset_id), self.total_supply(asset_id)?))
+        }
+
+        /// Returns the total supply of every registered asset, in registration order.
+        ///
+        /// Fails with the first error the runtime reports.
+        #[ink(message)]
+        pub fn snapshot_supplies(&self) -> Result<Vec<(AssetId, Balance)>> {
+            self.asset_ids
+                .iter()
+                .map(|&asset_id| Ok((asset_id, self.total_supply(asset_id)?)))
+                .collect()
+        }
+
+        /// Resets the escrow of the asset to the ERC20 this contract holds that is not
+        /// escrowed for other assets backed by the same ERC20, e.g. after tokens were
+        /// sent to the contract directly.
+        ///
+        /// Swaps must be paused.
+        #[ink(message)]
+        pub fn sync_escrow(&mut self, asset_id: u32) -> Result<()> {
+            self.ensure_owner()?;
+            if !self.paused {
+                return Err(Psp22Error::NotPaused);
+            }
+            let erc20 = self
+                .asset_pairs
+                .get(asset_id)
+                .ok_or(Psp22Error::AssetPairNotFound)?;
+            let token = ink::ToAccountId::to_account_id(&erc20);
+            let escrowed_elsewhere = self
+                .asset_ids
+                .iter()
+                .filter(|&&other| other != asset_id)
+                .filter(|&&other| {
+                    self.asset_pairs.get(other).is_some_and(|other_erc20| {
+                        ink::ToAccountId::to_account_id(&other_erc20) == token
+                    })
+                })
+                .map(|&other| self.escrowed_of(other))
+                .fold(0, Balance::saturating_add);
+            let old = self.escrowed_of(asset_id);
+            let new = erc20_calls::token_balance(&erc20, self.env().account_id())
+                .saturating_sub(escrowed_elsewhere);
+            self.escrowed.insert(asset_id, &new);
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(EscrowSynced {
+                event_seq,
+                asset_id,
+                old,
+                new,
+            });
+            Ok(())
+        }
+
+        /// Sends the fees accrued in the asset to the fee recipient, or splits them
+        /// according to `set_fee_split`.
+        ///
+        /// Returns the amount withdrawn.
+        #[ink(message)]
+        pub fn withdraw_fees(&mut self, asset_id: u32) -> Result<Balance> {
+            self.ensure_owner()?;
+            let accrued = self.accrued_fees_of(asset_id);
+            self.accrued_fees.remove(asset_id);
+            let Some((buyback, buyback_bps)) = self.fee_split else {
+                self.env()
+                    .extension()
+                    .transfer(asset_id, self.fee_recipient, accrued)?;
+                return Ok(accrued);
+            };
+            let buyback_share = accrued
+                .checked_mul(Balance::from(buyback_bps))
+                .ok_or(Psp22Error::Overflow)?
+                / BPS_DENOMINATOR;
+            for (recipient, share) in [
+                (self.fee_recipient, accrued - buyback_share),
+                (buyback, buyback_share),
+            ] {
+                if share > 0 {
+                    self.env()
+                        .extension()
+                        .transfer(asset_id, recipient, share)?;
+                }
+            }
+            Ok(accrued)
+        }
+
+        /// Deposits `amount` of the asset from the caller to pay swaps out of. The
+        /// contract must be approved to spend the asset.
+        #[ink(message)]
+        pub fn deposit_liquidity(&mut self, asset_id: u32, amount: Balance) -> Result<()> {
+            self.ensure_live()?;
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let provider = self.env().caller();
+            self.spend_allowance(asset_id, provider, self.env().account_id(), amount)?;
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(LiquidityDeposited {
+                event_seq,
+                asset_id,
+                amount,
+                provider,
+            });
+            Ok(())
+        }
+
+        /// Sends `amount` of the asset held to pay swaps out of to `to`. Accrued fees
+        /// cannot be withdrawn this way.
+        #[ink(message)]
+        pub fn withdraw_liquidity(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+            to: AccountId,
+        ) -> Result<()> {
+            self.ensure_owner()?;
+            let held = self.contract_asset_balance(asset_id)?;
+            if held.saturating_sub(self.accrued_fees_of(asset_id)) < amount {
+                return Err(Psp22Error::InsufficientLiquidity);
+            }
+            self.env().extension().transfer(asset_id, to, amount)?;
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(LiquidityWithdrawn {
+                event_seq,
+                asset_id,
+                amount,
+                to,
+            });
+            Ok(())
+        }
+
+        /// Sets the account the swaps into the asset pay out to instead of the caller,
+        /// such as a vault. Passing `None` pays the caller again.
+        ///
+        /// Swaps naming their own recipients still pay those, and refunds of ERC20
+        /// still go to the caller.
+        #[ink(message)]
+        pub fn set_default_recipient(&mut self, recipient: Option<AccountId>) -> Result<()> {
+            self.ensure_owner()?;
+            self.default_recipient = recipient;
+            Ok(())
+        }
+
+        /// Sets the native currency `rescue_native` and `swap_and_transfer_native` must
+        /// leave in the contract.
+        #[ink(message)]
+        pub fn set_native_reserve(&mut self, reserve: Balance) -> Result<()> {
+            self.ensure_owner()?;
+            self.native_reserve = reserve;
+            Ok(())
+        }
+
+        /// Sends `amount` of the native currency held by this contract to `to`, leaving
+        /// at least the native reserve behind.
+        #[ink(message)]
+        pub fn rescue_native(&mut self, to: AccountId, amount: Balance) -> Result<()> {
+            self.ensure_owner()?;
+            self.ensure_native_available(amount)?;
+            self.env()
+                .transfer(to, amount)
+                .map_err(|_| Psp22Error::NativeTransferFailed)?;
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(NativeRescued {
+                event_seq,
+                to,
+                amount,
+            });
+            Ok(())
+        }
+
+        /// Fails with `NativeTransferFailed` if this contract holds less than `amount` of
+        /// the native currency, or with `ReserveViolation` if sending `amount` would
+        /// leave less than the native reserve.
+        fn ensure_native_available(&self, amount: Balance) -> Result<()> {
+            let balance = self.env().balance();
+            if balance < amount {
+                return Err(Psp22Error::NativeTransferFailed);
+            }
+            if balance - amount < self.native_reserve {
+                return Err(Psp22Error::ReserveViolation);
+            }
+            Ok(())
+        }
+
+        /// Permanently disables the contract: afterwards every message that changes state
+        /// fails with `Terminated`.
+        ///
+        /// Fails with `EscrowNotEmpty` until the ERC20 escrowed for every asset has been
+        /// swapped back out.
+        #[ink(message)]
+        pub fn self_destruct_mode(&mut self) -> Result<()> {
+            self.ensure_owner()?;
+            if self
+                .asset_ids
+                .iter()
+                .any(|&asset_id| self.escrowed_of(asset_id) > 0)
+            {
+                return Err(Psp22Error::EscrowNotEmpty);
+            }
+            self.terminated = true;
+            Ok(())
+        }
+
+        /// Returns whether the contract was permanently disabled.
+        #[ink(message)]
+        pub fn is_terminated(&self) -> bool {
+            self.terminated
+        }
+
+        /// Returns the last swaps of `account`, oldest first.
+        ///
+        /// Only the most recent `MAX_RECENT_SWAPS` swaps are kept.
+        #[ink(message)]
+        pub fn recent_swaps(&self, account: AccountId) -> Vec<SwapReceipt> {
+            self.swap_history.get(account).unwrap_or_default()
+        }
+
+        /// Returns the number of events emitted so far, which is the sequence number the
+        /// next event carries.
+        #[ink(message)]
+        pub fn event_count(&self) -> u64 {
+            self.event_seq
+        }
+
+        /// Returns the current block number and timestamp, for clients evaluating
+        /// deadlines and cooldowns.
+        #[ink(message)]
+        pub fn now(&self) -> (BlockNumber, Timestamp) {
+            (self.env().block_number(), self.env().block_timestamp())
+        }
+
+        /// Returns the most topics an event may carry in this contract's environment.
+        ///
+        /// SCALE has no encoding for `usize`, so the limit is returned as a `u32`.
+        #[ink(message)]
+        pub fn max_event_topics(&self) -> u32 {
+            <crate::CustomEnvironment as ink::env::Environment>::MAX_EVENT_TOPICS as u32
+        }
+
+        /// Swaps `amount` of the pair's ERC20 for the asset at the pair's rate.
+        ///
+        /// Returns the amount of the asset sent to the caller, or to the default
+        /// recipient if one is set.
+        #[ink(message)]
+        pub fn swap_for_asset(&mut self, asset_id: u32, amount: Balance) -> Result<Balance> {
+            let config = self.live_config_of(asset_id)?;
+            let (received, output, fee) = self.pull_erc20(asset_id, &config, amount)?;
+            self.pay_out(asset_id, output)?;
+            self.record_swap(asset_id, received, output, fee);
+            Ok(output)
+        }
+
+        /// Performs the same swap as `swap_for_asset` at a lower cost: no receipt is
+        /// stored, no execution rate is recorded and a `RawSwap` event is emitted in
+        /// place of the receipt, after the `EscrowChanged` one.
+        #[ink(message)]
+        pub fn swap_for_asset_raw(&mut self, asset_id: u32, amount: Balance) -> Result<()> {
+            let config = self.live_config_of(asset_id)?;
+            let (received, output, fee) = self.pull_erc20(asset_id, &config, amount)?;
+            self.pay_out(asset_id, output)?;
+            self.change_escrow(asset_id, SwapDirection::ToAsset, received);
+            self.record_hop(asset_id, received, fee);
+            self.track_outflow(asset_id, output);
+            self.note_swap_time();
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(RawSwap {
+                event_seq,
+                asset_id,
+                amount_in: received,
+                amount_out: output,
+            });
+            Ok(())
+        }
+
+        /// Performs the same swap as `swap_for_asset`, but pays the output to
+        /// `recipient` and then calls its `SwapReceiver::on_swap_received`.
+        ///
+        /// Fails with `CallbackFailed`, reverting the swap, if the callback fails. Swaps
+        /// fail with `Reentrancy` while the callback runs.
+        #[ink(message)]
+        pub fn swap_and_call(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+            recipient: AccountId,
+        ) -> Result<Balance> {
+            let config = self.live_config_of(asset_id)?;
+            let (received, output, fee) = self.pull_erc20(asset_id, &config, amount)?;
+            self.env()
+                .extension()
+                .transfer(asset_id, recipient, output)?;
+            self.record_swap(asset_id, received, output, fee);
+            self.locked.set(&true);
+            let notified = receiver_calls::on_swap_received(recipient, asset_id, output);
+            self.locked.set(&false);
+            notified.ok_or(Psp22Error::CallbackFailed)?;
+            Ok(output)
+        }
+
+        /// Returns whether the reentrancy lock of `swap_and_call` is held.
+        ///
+        /// Outside of `swap_and_call` this is always `false`, as the lock is released
+        /// before the message returns, whether or not the callback succeeded.
+        #[ink(message)]
+        pub fn is_locked(&self) -> bool {
+            self.locked.get().unwrap_or_default()
+        }
+
+        /// Clears the reentrancy lock, should a bug have left it held.
+        ///
+        /// No path of this contract leaves the lock held, so this is a safety valve
+        /// that currently has nothing to clear; it only emits `LockForceReset`.
+        #[ink(message)]
+        pub fn force_unlock(&mut self) -> Result<()> {
+            self.ensure_owner()?;
+            self.locked.set(&false);
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(LockForceReset { event_seq });
+            Ok(())
+        }
+
+        /// Performs the same swap as `swap_for_asset`, but splits the output between two
+        /// recipients: `recipient_a` receives `bps_a` basis points of it and
+        /// `recipient_b` the rest, including any rounding dust.
+        #[ink(message)]
+        pub fn swap_for_asset_split(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+            recipient_a: AccountId,
+            bps_a: u16,
+            recipient_b: AccountId,
+        ) -> Result<Balance> {
+            if u128::from(bps_a) > BPS_DENOMINATOR {
+                return Err(Psp22Error::InvalidSplit);
+            }
+            let config = self.live_config_of(asset_id)?;
+            let (received, output, fee) = self.pull_erc20(asset_id, &config, amount)?;
+            let share_a = output
+                .checked_mul(Balance::from(bps_a))
+                .ok_or(Psp22Error::Overflow)?
+                / BPS_DENOMINATOR;
+            for (recipient, share) in [(recipient_a, share_a), (recipient_b, output - share_a)] {
+                if share > 0 {
+                    self.env()
+                        .extension()
+                        .transfer(asset_id, recipient, share)?;
+                }
+            }
+            self.record_swap(asset_id, received, output, fee);
+            Ok(output)
+        }
+
+        /// Performs the same swap as `swap_for_asset`, paying the referral cut of its fee
+        /// to `referrer`, if given.
+        ///
+        /// Fails with `SelfReferral` if the caller refers itself.
+        #[ink(message)]
+        pub fn swap_with_referral(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+            referrer: Option<AccountId>,
+        ) -> Result<Balance> {
+            if referrer == Some(self.env().caller()) {
+                return Err(Psp22Error::SelfReferral);
+            }
+            let config = self.live_config_of(asset_id)?;
+            let (received, output, fee) = self.pull_erc20(asset_id, &config, amount)?;
+            self.pay_out(asset_id, output)?;
+            let mut retained = fee;
+            if let Some(referrer) = referrer {
+                let kickback = fee
+                    .checked_mul(Balance::from(self.referral_bps))
+                    .ok_or(Psp22Error::Overflow)?
+                    / BPS_DENOMINATOR;
+                if kickback > 0 {
+                    self.env()
+                        .extension()
+                        .transfer(asset_id, referrer, kickback)?;
+                    let earned = self.referral_earnings_of(referrer);
+                    self.referral_earnings
+                        .insert(referrer, &earned.saturating_add(kickback));
+                    retained -= kickback;
+                }
+            }
+            self.record_swap(asset_id, received, output, retained);
+            Ok(output)
+        }
+
+        /// Swaps ERC20 for exactly `asset_out` of the asset, pulling `max_erc20_in` from
+        /// the caller and refunding what the swap does not need.
+        ///
+        /// Fails with `SlippageExceeded` if more than `max_erc20_in` is needed. Rounding
+        /// dust of the output is retained as part of the fee.
+        ///
+        /// Returns the ERC20 consumed by the swap and the ERC20 refunded.
+        #[ink(message)]
+        pub fn swap_for_exact_asset(
+            &mut self,
+            asset_id: u32,
+            asset_out: Balance,
+            max_erc20_in: Balance,
+        ) -> Result<(Balance, Balance)> {
+            let config = self.live_config_of(asset_id)?;
+            let needed = config.input_for(asset_out)?;
+            if needed > max_erc20_in {
+                return Err(Psp22Error::SlippageExceeded);
+            }
+            // The swap is checked for what it needs rather than what is pulled.
+            let (mut erc20, _, _) = self.quote_swap(asset_id, &config, needed)?;
+            let received = self.transfer_in(asset_id, &mut erc20, max_erc20_in)?;
+            // Tokens charging a fee on transfer may deliver less than is needed.
+            if received < needed {
+                return Err(Psp22Error::SlippageExceeded);
+            }
+            let caller = self.env().caller();
+            let refund = received - needed;
+            if refund > 0 {
+                erc20_calls::transfer(&mut erc20, caller, refund)
+                    .map_err(|_| Psp22Error::Erc20TransferFailed)?;
+            }
+            let fee = config.output_for(needed)? - asset_out;
+            self.pay_out(asset_id, asset_out)?;
+            self.record_swap(asset_id, needed, asset_out, fee);
+            Ok((needed, refund))
+        }
+
+        /// Performs each `(asset_id, amount)` swap in turn as `swap_for_asset` does, at
+        /// most `MAX_BATCH` at once.
+        ///
+        /// In `strict` mode the batch fails if any asset has no pair; otherwise such
+        /// entries are skipped and pay out `0`. Failures are reported as
+        /// `BatchItemFailed` with the index of the first failing entry.
+        ///
+        /// Returns the amount of the asset paid out by each swap.
+        #[ink(message)]
+        pub fn swap_batch(
+            &mut self,
+            swaps: Vec<(u32, Balance)>,
+            strict: bool,
+        ) -> Result<Vec<Balance>> {
+            self.ensure_live()?;
+            Self::ensure_batch_size(swaps.len())?;
+            if strict {
+                if let Some(index) = swaps
+                    .iter()
+                    .position(|&(asset_id, _)| !self.asset_pairs.contains(asset_id))
+                {
+                    return Err(Psp22Error::BatchItemFailed {
+                        index: index as u32,
+                    });
+                }
+            }
+            swaps
+                .into_iter()
+                .enumerate()
+                .map(|(index, (asset_id, amount))| {
+                    if !self.asset_pairs.contains(asset_id) {
+                        return Ok(0);
+                    }
+                    self.swap_for_asset(asset_id, amount)
+                        .map_err(|_| Psp22Error::BatchItemFailed {
+                            index: index as u32,
+                        })
+                })
+                .collect()
+        }
+
+        /// Returns the fees the `(asset_id, amount)` swaps would retain if performed in
+        /// turn now, at most `MAX_BATCH` at once. Nothing is swapped.
+        ///
+        /// Each fee is counted in its asset, so the sum is only meaningful for swaps
+        /// into assets of equal value.
+        #[ink(message)]
+        pub fn estimate_fees(&self, swaps: Vec<(u32, Balance)>) -> Result<Balance> {
+            Self::ensure_batch_size(swaps.len())?;
+            swaps
+                .into_iter()
+                .try_fold(0, |total: Balance, (asset_id, amount)| {
+                    if !self.asset_pairs.contains(asset_id) {
+                        return Err(Psp22Error::AssetPairNotFound);
+                    }
+                    let (_, fee) = self.live_config_of(asset_id)?.output_and_fee(amount)?;
+                    total.checked_add(fee).ok_or(Psp22Error::Overflow)
+                })
+        }
+
+        /// Performs the same swap as `swap_for_asset`, at most once per idempotency key
+        /// of the caller.
+        ///
+        /// Fails with `DuplicateRequest` if a swap of the caller with the same key
+        /// already succeeded. Keys are scoped to the caller, so other accounts cannot
+        /// use them up. Without a key, the swap is not deduplicated.
+        #[ink(message)]
+        pub fn swap_idempotent(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+            idempotency_key: Option<[u8; 32]>,
+        ) -> Result<Balance> {
+            if let Some(key) = idempotency_key {
+                if self.processed_requests.contains((self.env().caller(), key)) {
+                    return Err(Psp22Error::DuplicateRequest);
+                }
+            }
+            let output = self.swap_for_asset(asset_id, amount)?;
+            if let Some(key) = idempotency_key {
+                self.processed_requests
+                    .insert((self.env().caller(), key), &());
+            }
+            Ok(output)
+        }
+
+        /// Checks that the caller could swap `amount` of ERC20 for the asset right now,
+        /// without swapping.
+        ///
+        /// Runs the same pause, account, cap and output checks as `swap_for_asset`, and
+        /// checks that the caller approved this contract to spend `amount`.
+        #[ink(message)]
+        pub fn swap_preconditions(&self, asset_id: u32, amount: Balance) -> Result<()> {
+            let config = self.live_config_of(asset_id)?;
+            let (erc20, _, _) = self.quote_swap(asset_id, &config, amount)?;
+            self.ensure_approval_live(asset_id, self.env().caller())?;
+            let allowance =
+                erc20_calls::allowance(&erc20, self.env().caller(), self.own_account_id());
+            if allowance < amount {
+                return Err(Psp22Error::InsufficientAllowance);
+            }
+            Ok(())
+        }
+
+        /// Returns whether this contract currently holds enough of the asset to pay out
+        /// a swap of `amount` of ERC20.
+        #[ink(message)]
+        pub fn can_fill(&self, asset_id: u32, amount: Balance) -> Result<bool> {
+            if !self.asset_pairs.contains(asset_id) {
+                return Err(Psp22Error::AssetPairNotFound);
+            }
+            let (output, _) = self.live_config_of(asset_id)?.output_and_fee(amount)?;
+            Ok(output <= self.contract_asset_balance(asset_id)?)
+        }
+
+        /// Performs the same swap as `swap_for_asset`, failing with `SlippageExceeded`
+        /// if it would pay out less than `min_out` of the asset.
+        #[ink(message)]
+        pub fn swap_for_asset_min(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+            min_out: Balance,
+        ) -> Result<Balance> {
+            let (quoted, _) = self.live_config_of(asset_id)?.output_and_fee(amount)?;
+            Self::ensure_min_out(quoted, min_out)?;
+            let output = self.swap_for_asset(asset_id, amount)?;
+            // Tokens charging a fee on transfer buy less than quoted.
+            Self::ensure_min_out(output, min_out)?;
+            Ok(output)
+        }
+
+        /// Swaps `amount` of the backing ERC20 `erc20` for the asset.
+        ///
+        /// Fails with `UnknownBacking` unless `erc20` is registered as a backing of the
+        /// asset.
+        #[ink(message)]
+        pub fn swap_for_asset_with(
+            &mut self,
+            asset_id: u32,
+            erc20: AccountId,
+            amount: Balance,
+        ) -> Result<Balance> {
+            let backing = self
+                .asset_pairs
+                .get(asset_id)
+                .ok_or(Psp22Error::AssetPairNotFound)?;
+            if ink::ToAccountId::to_account_id(&backing) != erc20 {
+                return Err(Psp22Error::UnknownBacking);
+            }
+            self.swap_for_asset(asset_id, amount)
+        }
+
+        /// Swaps `amount` of ERC20 for the asset, first registering a pair backed by
+        /// `erc20_address` with the template settings if none exists.
+        ///
+        /// Only the owner may register a pair this way. `erc20_address` is ignored if the
+        /// pair is already registered.
+        #[ink(message)]
+        pub fn swap_or_create(
+            &mut self,
+            asset_id: u32,
+            erc20_address: Erc20Ref,
+            amount: Balance,
+        ) -> Result<Balance> {
+            if !self.asset_pairs.contains(asset_id) {
+                self.ensure_owner()?;
+                self.ensure_not_reserved(asset_id)?;
+                self.ensure_backing_unbound(asset_id, &erc20_address)?;
+                let decimals_override = self.pair_template.erc20_decimals_override;
+                self.ensure_decimals_supported(asset_id, &erc20_address, decimals_override)?;
+                self.register_pair(asset_id, &erc20_address);
+                self.store_pair_config(asset_id, self.pair_template.clone());
+            }
+            self.swap_for_asset(asset_id, amount)
+        }
+
+        /// Swaps up to `amount` of the pair's ERC20, filling only as much as the pair's
+        /// per-swap maximum allows.
+        ///
+        /// The unfilled remainder is never pulled from the caller. Returns the amount of
+        /// ERC20 taken in and the amount of the asset sent out.
+        #[ink(message)]
+        pub fn swap_for_asset_partial(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+        ) -> Result<(Balance, Balance)> {
+            let config = self.live_config_of(asset_id)?;
+            if !config.allow_partial {
+                return Err(Psp22Error::PartialFillNotAllowed);
+            }
+            let filled = config
+                .max_swap
+                .map_or(amount, |max_swap| amount.min(max_swap));
+            let (received, output, fee) = self.pull_erc20(asset_id, &config, filled)?;
+            self.pay_out(asset_id, output)?;
+            self.record_swap(asset_id, received, output, fee);
+            Ok((filled, output))
+        }
+
+        /// Swaps `amount` of the pair's ERC20 for the asset without reverting on failure.
+        ///
+        /// Returns `None` if the swap failed, in which case a `SwapFailed` event carrying
+        /// the reason is emitted and any ERC20 already pulled is handed back. Once the
+        /// contract is terminated it returns `None` without emitting anything.
+        #[ink(message)]
+        pub fn attempt_swap_for_asset(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+        ) -> Option<Balance> {
+            self.ensure_live().ok()?;
+            match self.swap_or_refund(asset_id, amount) {
+                Ok(output) => Some(output),
+                Err(error) => {
+                    let event_seq = self.next_event_seq();
+                    self.env().emit_event(SwapFailed {
+                        event_seq,
+                        caller: self.env().caller(),
+                        asset_id,
+                        direction: SwapDirection::ToAsset,
+                        reason: error.discriminant(),
+                    });
+                    None
+                }
+            }
+        }
+
+        /// Swaps `amount` of the pair's ERC20 and pays the caller out in native currency.
+        ///
+        /// Only available for pairs whose asset wraps the native currency: the asset
+        /// output stays with this contract and the equivalent native value is sent to
+        /// the caller instead.
+        #[ink(message)]
+        pub fn swap_and_transfer_native(
+            &mut self,
+            asset_id: u32,
+            amount: Balance,
+        ) -> Result<Balance> {
+            let config = self.live_config_of(asset_id)?;
+            if !config.is_native_wrapper {
+                return Err(Psp22Error::NotNativeWrapper);
+            }
+            let (received, output, fee) = self.pull_erc20(asset_id, &config, amount)?;
+            self.ensure_native_available(output)?;
+            self.env()
+                .transfer(self.env().caller(), output)
+                .map_err(|_| Psp22Error::NativeTransferFailed)?;
+            self.record_swap(asset_id, received, output, fee);
+            Ok(output)
+        }
+
+        /// Swaps `asset_amount` of the asset back for the ERC20 backing it, at the
+        /// pair's rate. The contract must be approved to spend the asset.
+        #[ink(message)]
+        pub fn swap_to_erc20(&mut self, asset_id: u32, asset_amount: Balance) -> Result<Balance> {
+            self.swap_to_erc20_min(asset_id, asset_amount, 0)
+        }
+
+        /// Performs the same swap as `swap_to_erc20`, failing with `SlippageExceeded`
+        /// if it would pay out less than `min_erc20_out`.
+        #[ink(message)]
+        pub fn swap_to_erc20_min(
+            &mut self,
+            asset_id: u32,
+            asset_amount: Balance,
+            min_erc20_out: Balance,
+        ) -> Result<Balance> {
+            self.ensure_caller_may_swap()?;
+            let mut erc20 = self
+                .asset_pairs
+                .get(asset_id)
+                .ok_or(Psp22Error::AssetPairNotFound)?;
+            let config = self.live_config_of(asset_id)?;
+            config.ensure_open()?;
+            let output = config.reverse_output_for(asset_amount)?;
+            Self::ensure_min_out(output, min_erc20_out)?;
+            if output == 0 {
+                return Err(Psp22Error::ZeroOutput);
+            }
+            if output > self.escrowed_of(asset_id) {
+                return Err(Psp22Error::InsufficientEscrow);
+            }
+
+            let caller = self.env().caller();
+            self.spend_allowance(asset_id, caller, self.own_account_id(), asset_amount)?;
+            erc20_calls::transfer(&mut erc20, caller, output)
+                .map_err(|_| Psp22Error::Erc20TransferFailed)?;
+            self.change_escrow(asset_id, SwapDirection::ToErc20, output);
+            self.push_receipt(SwapDirection::ToErc20, asset_id, asset_amount, output);
+            self.note_swap_time();
+            Ok(output)
+        }
+
+        /// Swaps the native currency transferred with the call for the asset.
+        ///
+        /// The native currency is first wrapped into the pair's ERC20, which must
+        /// implement `Erc20Trait::deposit`, and then swapped at the pair's rate.
+        #[ink(message, payable)]
+        pub fn swap_native_for_asset(&mut self, asset_id: u32) -> Result<Balance> {
+            let amount = self.env().transferred_value();
+            let config = self.live_config_of(asset_id)?;
+            let (erc20, output, fee) = self.quote_swap(asset_id, &config, amount)?;
+            erc20_calls::deposit(&erc20, amount).ok_or(Psp22Error::WrapFailed)?;
+            self.pay_out(asset_id, output)?;
+            self.record_swap(asset_id, amount, output, fee);
+            Ok(output)
+        }
+
+        /// Sends the output of a swap to the default recipient, if one is set, or else to
+        /// the caller.
+        fn pay_out(&mut self, asset_id: AssetId, output: Balance) -> Result<()> {
+            let recipient = self.default_recipient.unwrap_or(self.env().caller());
+            self.env().extension().transfer(asset_id, recipient, output)
+        }
+
+        /// Performs the same swap as `swap_for_asset`, but refunds the pulled ERC20 if
+        /// anything fails after it was received, leaving no partial effects behind.
+        fn swap_or_refund(&mut self, asset_id: AssetId, amount: Balance) -> Result<Balance> {
+            let config = self.live_config_of(asset_id)?;
+            let (mut erc20, received) = self.receive_erc20(asset_id, &config, amount)?;
+            let caller = self.env().caller();
+            let paid = Self::output_for_received(&config, received).and_then(|(output, fee)| {
+                self.pay_out(asset_id, output)?;
+                Ok((output, fee))
+            });
+            match paid {
+                Ok((output, fee)) => {
+                    self.record_swap(asset_id, received, output, fee);
+                    Ok(output)
+                }
+                Err(error) => {
+                    let refund_result = erc20_calls::transfer(&mut erc20, caller, received);
+                    assert!(refund_result.is_ok(), "refund_result {:?}", refund_result);
+                    Err(error)
+                }
+            }
+        }
+
+        /// Pulls `amount` of the pair's ERC20 from the caller into this contract.
+        ///
+        /// Returns the amount of ERC20 actually received, which is less than `amount`
+        /// for tokens charging a fee on transfer, along with the amount of the asset it
+        /// buys and the fee retained from that.
+        fn pull_erc20(
+            &mut self,
+            asset_id: AssetId,
+            config: &PairConfig,
+            amount: Balance,
+        ) -> Result<(Balance, Balance, Balance)> {
+            let (_, received) = self.receive_erc20(asset_id, config, amount)?;
+            let (output, fee) = Self::output_for_received(config, received)?;
+            Ok((received, output, fee))
+        }
+
+        /// Checks the swap and pulls `amount` of the pair's ERC20 from the caller,
+        /// returning the pair's ERC20 and the amount this contract received.
+        fn receive_erc20(
+            &mut self,
+            asset_id: AssetId,
+            config: &PairConfig,
+            amount: Balance,
+        ) -> Result<(Erc20Ref, Balance)> {
+            let (mut erc20, _, _) = self.quote_swap(asset_id, config, amount)?;
+            let received = self.transfer_in(asset_id, &mut erc20, amount)?;
+            Ok((erc20, received))
+        }
+
+        /// Pulls `amount` of `erc20`, the pair's ERC20, from the caller without checking
+        /// the swap, returning the amount this contract received.
+        fn transfer_in(
+            &mut self,
+            asset_id: AssetId,
+            erc20: &mut Erc20Ref,
+            amount: Balance,
+        ) -> Result<Balance> {
+            self.ensure_approval_live(asset_id, self.env().caller())?;
+            let contract = self.own_account_id();
+            let balance_before = erc20_calls::token_balance(erc20, contract);
+
+            // contract needs to be approved to spend funds
+            erc20_calls::transfer_from(erc20, self.env().caller(), contract, amount).map_err(
+                |error| match error {
+                    erc20::Error::InsufficientAllowance => Psp22Error::InsufficientAllowance,
+                    erc20::Error::InsufficientBalance => Psp22Error::TransferFromFailed,
+                },
+            )?;
+
+            let received =
+                erc20_calls::token_balance(erc20, contract).saturating_sub(balance_before);
+            if received == 0 {
+                return Err(Psp22Error::UnexpectedTransferAmount);
+            }
+            Ok(received)
+        }
+
+        /// Splits the output `received` ERC20 buys into the part paid out and the fee,
+        /// failing with `ZeroOutput` if nothing would be paid out.
+        fn output_for_received(
+            config: &PairConfig,
+            received: Balance,
+        ) -> Result<(Balance, Balance)> {
+            let (output, fee) = config.output_and_fee(received)?;
+            if output == 0 {
+                return Err(Psp22Error::ZeroOutput);
+            }
+            Ok((output, fee))
+        }
+
+        /// Returns the amount of the asset held by this contract.
+        fn contract_asset_balance(&self, asset_id: AssetId) -> Result<Balance> {
+            self.balance_of(asset_id, self.env().account_id())
+        }
+
+        /// Checks that the caller may swap `amount` of ERC20 for the asset.
+        ///
+        /// Returns the pair's ERC20 along with the amount of the asset owed in exchange
+        /// and the fee retained from it.
+        fn quote_swap(
+            &self,
+            asset_id: AssetId,
+            config: &PairConfig,
+            amount: Balance,
+        ) -> Result<(Erc20Ref, Balance, Balance)> {
+            self.ensure_caller_may_swap()?;
+            config.ensure_open()?;
+            if config.max_swap.is_some_and(|max_swap| amount > max_swap) {
+                return Err(Psp22Error::AboveMaxSwap);
+            }
+            if let Some((threshold, blocks)) = self.cooldowns.get(asset_id) {
+                let cooling = self
+                    .last_large_swap
+                    .get(asset_id)
+                    .is_some_and(|last| self.env().block_number() < last.saturating_add(blocks));
+                if amount > threshold && cooling {
+                    return Err(Psp22Error::CooldownActive);
+                }
+            }
+            let erc20 = self
+                .asset_pairs
+                .get(asset_id)
+                .ok_or(Psp22Error::AssetPairNotFound)?;
+            let (output, fee) = config.output_and_fee(amount)?;
+            if output == 0 {
+                return Err(Psp22Error::ZeroOutput);
+            }
+            Ok((erc20, output, fee))
+        }
+
+        /// Returns the sequence number for the event about to be emitted and advances it,
+        /// so indexers can detect missed or reordered events.
+        fn next_event_seq(&mut self) -> u64 {
+            let seq = self.event_seq;
+            self.event_seq += 1;
+            seq
+        }
+
+        /// Returns this contract's account id from storage rather than the host.
+        fn own_account_id(&self) -> AccountId {
+            debug_assert_eq!(self.contract_id, self.env().account_id());
+            self.contract_id
+        }
+
+        /// Moves `value` of the asset from `from` to `to` out of the allowance `from`
+        /// granted this contract, failing with `ApprovalExpired` if it was timed and
+        /// has expired.
+        fn spend_allowance(
+            &mut self,
+            asset_id: AssetId,
+            from: AccountId,
+            to: AccountId,
+            value: Balance,
+        ) -> Result<()> {
+            self.ensure_approval_live(asset_id, from)?;
+            self.env()
+                .extension()
+                .transfer_from(asset_id, from, to, value)
+        }
+
+        /// Fails with `ApprovalExpired` if the approvals `owner` granted this contract for
+        /// the pair of the asset were timed and have expired.
+        fn ensure_approval_live(&self, asset_id: AssetId, owner: AccountId) -> Result<()> {
+            if self
+                .approval_expiries
+                .get((asset_id, owner, self.own_account_id()))
+                .is_some_and(|expiry| self.env().block_timestamp() >= expiry)
+            {
+                return Err(Psp22Error::ApprovalExpired);
+            }
+            Ok(())
+        }
+
+        /// Fails with `Erc20AlreadyBound` if backings must be unique and `erc20` backs
+        /// an asset other than `asset_id`.
+        fn ensure_backing_unbound(&self, asset_id: AssetId, erc20: &Erc20Ref) -> Result<()> {
+            if !self.unique_backing {
+                return Ok(());
+            }
+            let token = ink::ToAccountId::to_account_id(erc20);
+            let bound = self.asset_ids.iter().any(|&other| {
+                other != asset_id
+                    && self
+                        .asset_pairs
+                        .get(other)
+                        .is_some_and(|erc20| ink::ToAccountId::to_account_id(&erc20) == token)
+            });
+            if bound {
+                return Err(Psp22Error::Erc20AlreadyBound);
+            }
+            Ok(())
+        }
+
+        /// Fails with `ReservedAssetId` if `asset_id` lies in the reserved range.
+        fn ensure_not_reserved(&self, asset_id: AssetId) -> Result<()> {
+            let (start, end) = self.reserved_range;
+            if (start..end).contains(&asset_id) {
+                return Err(Psp22Error::ReservedAssetId);
+            }
+            Ok(())
+        }
+
+        /// Records a completed swap: escrows its input, accrues its fee, appends a
+        /// receipt to the caller's swap history, evicting the oldest one once
+        /// `MAX_RECENT_SWAPS` are kept, and stores its execution rate.
+        fn record_swap(
+            &mut self,
+            asset_id: AssetId,
+            amount_in: Balance,
+            amount_out: Balance,
+            fee: Balance,
+        ) {
+            self.change_escrow(asset_id, SwapDirection::ToAsset, amount_in);
+            self.record_hop(asset_id, amount_in, fee);
+            self.track_outflow(asset_id, amount_out);
+            self.push_receipt(SwapDirection::ToAsset, asset_id, amount_in, amount_out);
+            self.record_rate(asset_id, amount_in, amount_out);
+            self.note_swap_time();
+        }
+
+        /// Stores the rate a swap into the asset executed at and adds it to the TWAP.
+        fn record_rate(&mut self, asset_id: AssetId, amount_in: Balance, amount_out: Balance) {
+            self.last_execution_rates
+                .insert(asset_id, &(amount_out, amount_in));
+            self.accrue_twap(asset_id, amount_in, amount_out);
+        }
+
+        /// Adds a swap to the asset's TWAP, dropping the swaps whose rates no longer
+        /// stand within `TWAP_WINDOW` and the oldest one once `MAX_TWAP_POINTS` are kept.
+        fn accrue_twap(&mut self, asset_id: AssetId, amount_in: Balance, amount_out: Balance) {
+            if amount_in == 0 {
+                return;
+            }
+            let now = self.env().block_timestamp();
+            let window_start = now.saturating_sub(TWAP_WINDOW);
+            let mut points = self.twap_points.get(asset_id).unwrap_or_default();
+            // The last swap before the window still stands at its start.
+            if let Some(standing) = points.iter().rposition(|&(at, _, _)| at <= window_start) {
+                points.drain(..standing);
+            }
+            if points.len() == MAX_TWAP_POINTS {
+                points.remove(0);
+            }
+            points.push((now, amount_out, amount_in));
+            self.twap_points.insert(asset_id, &points);
+        }
+
+        /// Records a swap into the asset apart from its escrow, receipt and outflow:
+        /// accrues its fee and counts it towards the cooldown.
+        fn record_hop(&mut self, asset_id: AssetId, amount_in: Balance, fee: Balance) {
+            if fee > 0 {
+                let accrued = self.accrued_fees_of(asset_id);
+                self.accrued_fees.insert(asset_id, &(accrued + fee));
+            }
+            if self
+                .cooldowns
+                .get(asset_id)
+                .is_some_and(|(threshold, _)| amount_in > threshold)
+            {
+                self.last_large_swap
+                    .insert(asset_id, &self.env().block_number());
+            }
+        }
+
+        /// Appends a receipt to the caller's swap history, evicting the oldest one once
+        /// `MAX_RECENT_SWAPS` are kept.
+        fn push_receipt(
+            &mut self,
+            direction: SwapDirection,
+            asset_id: AssetId,
+            amount_in: Balance,
+            amount_out: Balance,
+        ) {
+            let caller = self.env().caller();
+            let mut history = self.swap_history.get(caller).unwrap_or_default();
+            if history.len() == MAX_RECENT_SWAPS {
+                history.remove(0);
+            }
+            history.push(SwapReceipt {
+                asset_id,
+                direction,
+                amount_in,
+                amount_out,
+                timestamp: self.env().block_timestamp(),
+            });
+            self.swap_history.insert(caller, &history);
+        }
+
+        /// Records that the caller swapped now, for rate limiting.
+        fn note_swap_time(&mut self) {
+            let now = self.env().block_timestamp();
+            self.last_swap_at
+                .insert(self.env().caller(), &(self.rate_limit_epoch, now));
+        }
+
+        /// Adds `amount` to the outflow of the asset in the current window, tripping the
+        /// circuit breaker once it exceeds `max_outflow`.
+        fn track_outflow(&mut self, asset_id: AssetId, amount: Balance) {
+            let Some(max_outflow) = self.max_outflow else {
+                return;
+            };
+            let now = self.env().block_timestamp();
+            let (start, outflow) = self
+                .outflows
+                .get(asset_id)
+                .filter(|(start, _)| now < start.saturating_add(self.outflow_window))
+                .unwrap_or((now, 0));
+            let outflow = outflow.saturating_add(amount);
+            self.outflows.insert(asset_id, &(start, outflow));
+            if outflow > max_outflow && !self.paused {
+                self.paused = true;
+                let event_seq = self.next_event_seq();
+                self.env().emit_event(CircuitBreakerTripped {
+                    event_seq,
+                    asset_id,
+                    outflow,
+                });
+            }
+        }
+
+        /// Moves the tracked escrow of an asset by `amount` and emits `EscrowChanged`.
+        fn change_escrow(&mut self, asset_id: AssetId, direction: SwapDirection, amount: Balance) {
+            let escrowed = self.escrowed_of(asset_id);
+            let new_total = match direction {
+                SwapDirection::ToAsset => escrowed + amount,
+                SwapDirection::ToErc20 => escrowed - amount,
+            };
+            self.escrowed.insert(asset_id, &new_total);
+            let event_seq = self.next_event_seq();
+            self.env().emit_event(EscrowChanged {
+                event_seq,
+                asset_id,
+                direction,
+                amount,
+                new_total,
+            });
+        }
+
+        // PSP22 Metadata interfaces
+
+        /// Returns the token name of the specified asset.
+        #[ink(message, selector = 0x3d261bd4)]
+        pub fn token_name(&self, asset_id: u32) -> Result<Vec<u8>> {
+            if let Some(metadata) = self.metadata_cache.get(asset_id) {
+                return Ok(metadata.name);
+            }
+            Self::ensure_metadata_len(self.env().extension().token_name(asset_id)?)
+        }
+
+        /// Returns the token symbol of the specified asset.
+        #[ink(message, selector = 0x34205be5)]
+        pub fn token_symbol(&self, asset_id: u32) -> Result<Vec<u8>> {
+            if let Some(metadata) = self.metadata_cache.get(asset_id) {
+                return Ok(metadata.symbol);
+            }
+            Self::ensure_metadata_len(self.env().extension().token_symbol(asset_id)?)
+        }
+
+        /// Returns the token symbol of the specified asset, storing it on first use so
+        /// that later calls are answered without the extension. Unlike
+        /// `cache_metadata`, the name and decimals are not fetched.
+        ///
+        /// Nothing is stored once the contract is terminated.
+        #[ink(message)]
+        pub fn token_symbol_cached(&mut self, asset_id: u32) -> Result<Vec<u8>> {
+            if let Some(metadata) = self.metadata_cache.get(asset_id) {
+                return Ok(metadata.symbol);
+            }
+            if let Some(symbol) = self.symbol_cache.get(asset_id) {
+                return Ok(symbol);
+            }
+            let symbol = Self::ensure_metadata_len(self.env().extension().token_symbol(asset_id)?)?;
+            if !self.terminated {
+                self.symbol_cache.insert(asset_id, &symbol);
+            }
+            Ok(symbol)
+        }
+
+        /// Returns the token decimals of the specified asset.
+        #[ink(message, selector = 0x7271b782)]
+        pub fn token_decimals(&self, asset_id: u32) -> Result<u8> {
+            if let Some(metadata) = self.metadata_cache.get(asset_id) {
+                return Ok(metadata.decimals);
+            }
+            self.env().extension().token_decimals(asset_id)
+        }
+
+        /// Fetches the metadata of the specified asset from the runtime and stores it,
+        /// so that later metadata queries and unit-mode pricing are answered without
+        /// calling the extension. Calling it again refreshes the stored metadata.
+        ///
+        /// Only the owner may cache metadata.
+        #[ink(message)]
+        pub fn cache_metadata(&mut self, asset_id: u32) -> Result<()> {
+            self.ensure_owner()?;
+            let extension = || self.env().extension();
+            let metadata = TokenMetadata {
+                name: Self::ensure_metadata_len(extension().token_name(asset_id)?)?,
+                symbol: Self::ensure_metadata_len(extension().token_symbol(asset_id)?)?,
+                decimals: extension().token_decimals(asset_id)?,
+            };
+            self.metadata_cache.insert(asset_id, &metadata);
+            Ok(())
+        }
+
+        /// Drops the metadata and symbol stored for the specified asset, so that they
+        /// are fetched from the runtime again.
+        #[ink(message)]
+        pub fn invalidate_metadata(&mut self, asset_id: u32) -> Result<()> {
+            self.ensure_owner()?;
+            self.metadata_cache.remove(asset_id);
+            self.symbol_cache.remove(asset_id);
+            Ok(())
+        }
+
+        /// Returns whether the metadata of the specified asset is cached.
+        #[ink(message)]
+        pub fn is_metadata_cached(&self, asset_id: u32) -> bool {
+            self.metadata_cache.contains(asset_id)
+        }
+
+        // PSP22 interface queries
+
+        /// Returns the total token supply of the specified asset.
+        #[ink(message, selector = 0x162df8c2)]
+        pub fn total_supply(&self, asset_id: u32) -> Result<Balance> {
+            self.env().extension().total_supply(asset_id)
+        }
+
+        /// Returns the account balance for the specified asset & owner.
+        #[ink(message, selector = 0x6568382f)]
+        pub fn balance_of(&self, asset_id: u32, owner: AccountId) -> Result<Balance> {
+            self.env().extension().balance_of(asset_id, owner)
+        }
+
+        /// Returns the balance of `a` minus the balance of `b` in the asset.
+        ///
+        /// Fails with `Overflow` if the difference does not fit an `i128`.
+        #[ink(message)]
+        pub fn balance_delta(&self, asset_id: u32, a: AccountId, b: AccountId) -> Result<i128> {
+            let balance_a = self.balance_of(asset_id, a)?;
+            let balance_b = self.balance_of(asset_id, b)?;
+            let magnitude =
+                i128::try_from(balance_a.abs_diff(balance_b)).map_err(|_| Psp22Error::Overflow)?;
+            Ok(if balance_a >= balance_b {
+                magnitude
+            } else {
+                -magnitude
+            })
+        }
+
+        /// Returns the account balance for the specified asset & owner along with the
+        /// asset's decimals, so that clients can format it without a second call.
+        #[ink(message)]
+        pub fn balance_of_scaled(&self, asset_id: u32, owner: AccountId) -> Result<(Balance, u8)> {
+            Ok((
+                self.balance_of(asset_id, owner)?,
+                self.token_decimals(asset_id)?,
+            ))
+        }
+
+        /// Returns the balances of the specified asset of each of `owners`, at most
+        /// `MAX_BATCH` at once.
+        #[ink(message)]
+        pub fn balances_of(&self, asset_id: u32, owners: Vec<AccountId>) -> Result<Vec<Balance>> {
+            Self::ensure_batch_size(owners.len())?;
+            owners
+                .into_iter()
+                .map(|owner| self.balance_of(asset_id, owner))
+                .collect()
+        }
+
+        /// Returns the balance of `owner` in each of `asset_ids`, at most `MAX_BATCH` at
+        /// once.
+        #[ink(message)]
+        pub fn portfolio_value(
+            &self,
+            owner: AccountId,
+            asset_ids: Vec<u32>,
+        ) -> Result<Vec<(u32, Balance)>> {
+            Self::ensure_batch_size(asset_ids.len())?;
+            asset_ids
+                .into_iter()
+                .map(|asset_id| Ok((asset_id, self.balance_of(asset_id, owner)?)))
+                .collect()
+        }
+
+        /// Returns the amount which `spender` is still allowed to withdraw from `owner`
+        /// for the specified asset.
+        #[ink(message, selector = 0x4d47d921)]
+        pub fn allowance(
+            &self,
+            asset_id: u32,
+            owner: AccountId,
+            spender: AccountId,
+        ) -> Result<Balance> {
+            self.env().extension().allowance(asset_id, owner, spender)
+        }
+
+        // PSP22 transfer
+
+        /// Transfers `value` amount of specified asset from this contract's holdings to
+        /// the account `to`.
+        ///
+        /// The holdings pay swaps out and hold the accrued fees, so only the owner may
+        /// move them.
+        #[ink(message, selector = 0xdb20f9f5)]
+        pub fn transfer(&mut self, asset_id: u32, to: AccountId, value: Balance) -> Result<()> {
+            self.ensure_owner()?;
+            self.env().extension().transfer(asset_id, to, value)
+        }
+
+        // PSP22 transfer_from
+
+        /// Transfers `value` amount of specified asset on the behalf of `from` to the
+        /// account `to`.
+        ///
+        /// The allowance spent is the one `from` granted this contract, which holders
+        /// grant for reverse swaps and deposits, so only `from` itself may spend it.
+        /// Fails with `NotAllowanceHolder` for any other caller.
+        #[ink(message, selector = 0x54b3c76e)]
+        pub fn transfer_from(
+            &mut self,
+            asset_id: u32,
+            from: AccountId,
+            to: AccountId,
+            value: Balance,
+        ) -> Result<()> {
+            self.ensure_live()?;
+            if from != self.env().caller() {
+                return Err(Psp22Error::NotAllowanceHolder);
+            }
+            self.spend_allowance(asset_id, from, to, value)
+        }
+
+        // PSP22 approve
+
+        /// Allows `spender` to withdraw from this contract's holdings multiple times, up
+        /// to the `value` amount of the specified asset. Only the owner may grant it.
+        #[ink(message, selector = 0xb20f1bbd)]
+        pub fn approve(&mut self, asset_id: u32, spender: AccountId, value: Balance) -> Result<()> {
+            self.ensure_owner()?;
+            self.env().extension().approve(asset_id, spender, value)
+        }
+
+        /// Records that the approval of at least `value` of the asset the caller granted
+        /// `spender`, which must be this contract, expires at `expiry`.
+        ///
+        /// From `expiry` on this contract fails with `ApprovalExpired` instead of
+        /// spending the caller's allowances for the pair: the asset in reverse swaps,
+        /// deposits and `transfer_from`, and the pair's ERC20 in swaps into the asset.
+        /// Fails with `UnsupportedSpender` for any other spender, whose use of the
+        /// allowance this contract never sees, and with `InsufficientAllowance` if the
+        /// caller has not approved `value` yet.
+        #[ink(message)]
+        pub fn set_timed_approval(
+            &mut self,
+            asset_id: u32,
+            spender: AccountId,
+            value: Balance,
+            expiry: Timestamp,
+        ) -> Result<()> {
+            self.ensure_live()?;
+            let contract = self.own_account_id();
+            if spender != contract {
+                return Err(Psp22Error::UnsupportedSpender);
+            }
+            let caller = self.env().caller();
+            if self.allowance(asset_id, caller, contract)? < value {
+                return Err(Psp22Error::InsufficientAllowance);
+            }
+            self.approval_expiries
+                .insert((asset_id, caller, contract), &expiry);
+            Ok(())
+        }
+
+        /// Performs the same approval as `approve`, but resets the allowance to zero
+        /// first, as some tokens require before an allowance can be changed. Only the
+        /// owner may grant it.
+        #[ink(message)]
+        pub fn safe_approve(
+            &mut self,
+            asset_id: u32,
+            spender: AccountId,
+            value: Balance,
+        ) -> Result<()> {
+            self.ensure_owner()?;
+            self.approve(asset_id, spender, 0)?;
+            self.approve(asset_id, spender, value)
+        }
+
+        // PSP22 increase_allowance
+
+        /// Atomically increases the allowance of this contract's holdings of the
+        /// specified asset granted to `spender`. Only the owner may grant it.
+        #[ink(message, selector = 0x96d6b57a)]
+        pub fn increase_allowance(
+            &mut self,
+            asset_id: u32,
+            spender: AccountId,
+            value: Balance,
+        ) -> Result<()> {
+            self.ensure_owner()?;
+            self.env()
+                .extension()
+                .increase_allowance(asset_id, spender, value)
+        }
+
+        // PSP22 decrease_allowance
+
+        /// Atomically decreases the allowance of this contract's holdings of the
+        /// specified asset granted to `spender`. Only the owner may change it.
+        #[ink(message, selector = 0xfecb57d5)]
+        pub fn decrease_allowance(
+            &mut self,
+            asset_id: u32,
+            spender: AccountId,
+            value: Balance,
+        ) -> Result<()> {
+            self.ensure_owner()?;
+            self.env()
+                .extension()
+                .decrease_allowance(asset_id, spender, value)
+        }
+
+        /// Increases the allowance of this contract's holdings of the asset granted to
+        /// `spender` by `extra`, as `increase_allowance` does. Only the owner may grant
+        /// it.
+        ///
+        /// Returns the allowance after the increase.
+        #[ink(message)]
+        pub fn top_up_allowance(
+            &mut self,
+            asset_id: u32,
+            spender: AccountId,
+            extra: Balance,
+        ) -> Result<Balance> {
+            self.ensure_owner()?;
+            self.increase_allowance(asset_id, spender, extra)?;
+            self.allowance(asset_id, self.env().account_id(), spender)
+        }
+
+        /// Approves each `(asset_id, spender, value)` in turn from this contract's
+        /// holdings, at most `MAX_BATCH` at once. Only the owner may grant these
+        /// allowances.
+        ///
+        /// If any approval fails, `BatchItemFailed` is returned with the index of the
+        /// failing one, reverting those already made.
+        #[ink(message)]
+        pub fn approve_many(&mut self, approvals: Vec<(u32, AccountId, Balance)>) -> Result<()> {
+            self.ensure_owner()?;
+            Self::ensure_batch_size(approvals.len())?;
+            for (index, (asset_id, spender, value)) in approvals.into_iter().enumerate() {
+                self.env()
+                    .extension()
+                    .approve(asset_id, spender, value)
+                    .map_err(|_| Psp22Error::BatchItemFailed {
+                        index: index as u32,
+                    })?;
+            }
+            Ok(())
+        }
+
+        /// Resets each `(asset_id, spender)` allowance of this contract's holdings to
+        /// zero, at most `MAX_BATCH` at once. Only the owner may revoke them.
+        ///
+        /// Fails with `BatchItemFailed` carrying the index of the first entry that
+        /// could not be reset.
+        #[ink(message)]
+        pub fn revoke_allowances(
+            &mut self,
+            assets_and_spenders: Vec<(u32, AccountId)>,
+        ) -> Result<()> {
+            self.ensure_owner()?;
+            Self::ensure_batch_size(assets_and_spenders.len())?;
+            for (index, (asset_id, spender)) in assets_and_spenders.into_iter().enumerate() {
+                self.env()
+                    .extension()
+                    .approve(asset_id, spender, 0)
+                    .map_err(|_| Psp22Error::BatchItemFailed {
+                        index: index as u32,
+                    })?;
+            }
+            Ok(())
+        }
+
+        /// Drops the pair of the asset along with its settings, swap statistics and
+        /// cached metadata, so a pair registered for it later starts afresh.
+        ///
+        /// Accrued fees persist, as they are owed to the fee recipient and can still be
+        /// withdrawn with `withdraw_fees`. Allowances and their expiries are not tied to
+        /// the pair and persist too.
+        fn remove_pair(&mut self, asset_id: AssetId) {
+            self.asset_pairs.remove(asset_id);
+            self.pair_configs.remove(asset_id);
+            self.price_bands.remove(asset_id);
+            self.cooldowns.remove(asset_id);
+            self.last_large_swap.remove(asset_id);
+            self.outflows.remove(asset_id);
+            self.last_execution_rates.remove(asset_id);
+            self.twap_points.remove(asset_id);
+            self.metadata_cache.remove(asset_id);
+            self.symbol_cache.remove(asset_id);
+            self.asset_ids.retain(|&id| id != asset_id);
+        }
+
+        /// Maps `asset_id` to the ERC20 contract backing it.
+        fn register_pair(&mut self, asset_id: AssetId, erc20: &Erc20Ref) {
+            if !self.asset_pairs.contains(asset_id) {
+                self.asset_ids.push(asset_id);
+            }
+            self.asset_pairs.insert(asset_id, erc20);
+        }
+
+        /// Fails with `DecimalsOutOfRange` if the decimals of the asset and `erc20` are
+        /// more than `MAX_DECIMALS_GAP` apart. Passes if either is unknown.
+        fn ensure_decimals_supported(
+            &self,
+            asset_id: AssetId,
+            erc20: &Erc20Ref,
+            erc20_decimals_override: Option<u8>,
+        ) -> Result<()> {
+            let asset_decimals = self.env().extension().token_decimals(asset_id).ok();
+            let erc20_decimals = erc20_calls::decimals(erc20).or(erc20_decimals_override);
+            if let (Some(asset_decimals), Some(erc20_decimals)) = (asset_decimals, erc20_decimals) {
+                if asset_decimals.abs_diff(erc20_decimals) > MAX_DECIMALS_GAP {
+                    return Err(Psp22Error::DecimalsOutOfRange);
+                }
+            }
+            Ok(())
+        }
+
+        /// Returns the settings of a pair, falling back to the defaults for pairs that
+        /// have not been configured yet.
+        fn pair_config_of(&self, asset_id: AssetId) -> PairConfig {
+            self.pair_configs.get(asset_id).unwrap_or_default()
+        }
+
+        /// Stores the settings of a pair, bumping its config version.
+        fn store_pair_config(&mut self, asset_id: AssetId, config: PairConfig) {
+            let config = PairConfig {
+                config_version: self.pair_config_of(asset_id).config_version.wrapping_add(1),
+                ..config
+            };
+            self.pair_configs.insert(asset_id, &config);
+        }
+
+        /// Returns the settings of a pair to swap with, taking the rate from the pair's
+        /// oracle if it has one.
+        ///
+        /// Fails with `StaleOracle` if the oracle rate is older than `max_oracle_age`,
+        /// and with `PriceOutOfBand` if it strays too far from the pair's reference
+        /// rate.
+        fn live_config_of(&self, asset_id: AssetId) -> Result<PairConfig> {
+            let mut config = self.pair_config_of(asset_id);
+            if let Some(oracle) = config.oracle {
+                if let Some(max_age) = config.max_oracle_age {
+                    let updated = oracle_calls::last_updated(oracle, asset_id)
+                        .ok_or(Psp22Error::OracleFailed)?;
+                    let age = self.env().block_timestamp().saturating_sub(updated);
+                    if age > max_age.saturating_mul(1_000) {
+                        return Err(Psp22Error::StaleOracle);
+                    }
+                }
+                let (numerator, denominator) =
+                    oracle_calls::get_rate(oracle, asset_id).ok_or(Psp22Error::OracleFailed)?;
+                config.rate_numerator = numerator;
+                config.rate_denominator = denominator;
+                if let Some((reference_rate, max_deviation_bps)) = self.price_bands.get(asset_id) {
+                    config.ensure_in_band(reference_rate, max_deviation_bps)?;
+                }
+            }
+            if config.unit_mode {
+                let asset_decimals = self.token_decimals(asset_id)?;
+                let erc20_decimals = self.erc20_decimals(asset_id)?;
+                let scale = 10u128
+                    .checked_pow(u32::from(asset_decimals.abs_diff(erc20_decimals)))
+                    .ok_or(Psp22Error::Overflow)?;
+                let part = if asset_decimals >= erc20_decimals {
+                    &mut config.rate_numerator
+                } else {
+                    &mut config.rate_denominator
+                };
+                *part = part.checked_mul(scale).ok_or(Psp22Error::Overflow)?;
+            }
+            Ok(config)
+        }
+
+        /// Fails with `Terminated` once the contract was permanently disabled.
+        fn ensure_live(&self) -> Result<()> {
+            if self.terminated {
+                return Err(Psp22Error::Terminated);
+            }
+            Ok(())
+        }
+
+        /// Fails with `NotOwner` unless the caller is the contract owner.
+        fn ensure_owner(&self) -> Result<()> {
+            self.ensure_live()?;
+            if self.env().caller() != self.owner {
+                return Err(Psp22Error::NotOwner);
+            }
+            Ok(())
+        }
+
+        /// Fails with `NotPauser` unless the caller is the owner or the pauser.
+        fn ensure_pauser(&self) -> Result<()> {
+            self.ensure_live()?;
+            let caller = self.env().caller();
+            if caller != self.owner && caller != self.pauser {
+                return Err(Psp22Error::NotPauser);
+            }
+            Ok(())
+        }
+
+        /// Checks that swaps are not paused and that the caller passes the account
+        /// restrictions and rate limit on swapping.
+        fn ensure_caller_may_swap(&self) -> Result<()> {
+            self.ensure_live()?;
+            if self.is_locked() {
+                return Err(Psp22Error::Reentrancy);
+            }
+            if self.paused {
+                return Err(Psp22Error::Paused);
+            }
+            let caller = self.env().caller();
+            if self.blocklist.get(caller).unwrap_or_default() {
+                return Err(Psp22Error::Blocked);
+            }
+            if self.allowlist_enabled && !self.allowlist.get(caller).unwrap_or_default() {
+                return Err(Psp22Error::NotAllowlisted);
+            }
+            if let Some((epoch, last_swap)) = self.last_swap_at.get(caller) {
+                let now = self.env().block_timestamp();
+                if epoch == self.rate_limit_epoch
+                    && now < last_swap.saturating_add(self.swap_interval)
+                {
+                    return Err(Psp22Error::RateLimited);
+                }
+            }
+            Ok(())
+        }
+
+        /// Fails with `BatchTooLarge` if a batch holds more than `MAX_BATCH` entries.
+        fn ensure_batch_size(len: usize) -> Result<()> {
+            if len > MAX_BATCH {
+                return Err(Psp22Error::BatchTooLarge);
+            }
+            Ok(())
+        }
+
+        /// Passes `metadata` through, failing with `MetadataTooLong` if it exceeds
+        /// `MAX_METADATA_LEN` bytes.
+        fn ensure_metadata_len(metadata: Vec<u8>) -> Result<Vec<u8>> {
+            if metadata.len() > MAX_METADATA_LEN {
+                return Err(Psp22Error::MetadataTooLong);
+            }
+            Ok(metadata)
+        }
+
+        /// Fails with `SlippageExceeded` if `output` is below `min_out`.
+        fn ensure_min_out(output: Balance, min_out: Balance) -> Result<()> {
+            if output < min_out {
+                return Err(Psp22Error::SlippageExceeded);
+            }
+            Ok(())
+        }
+    }
+
+    /// Conveniences for host-side callers, left out of the Wasm build.
+    #[cfg(feature = "std")]
+    impl Psp22Extension {
+        /// Returns the token name of the specified asset decoded as UTF-8.
+        pub fn token_name_string(&self, asset_id: u32) -> Result<String> {
+            String::from_utf8(self.token_name(asset_id)?).map_err(|_| Psp22Error::InvalidUtf8)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use ink::env::{
+            call::FromAccountId,
+            test::{default_accounts, get_account_balance, set_account_balance, set_callee},
+        };
+
+        type Env = crate::CustomEnvironment;
+
+        /// In-memory stand-in for the ERC20 contracts backing the asset pairs.
+        pub mod erc20_mock {
+            use super::{AccountId, Balance, Env, Erc20Ref};
+            use std::{cell::RefCell, collections::HashMap};
+
+            #[derive(Default)]
+            struct Token {
+                balances: HashMap<AccountId, Balance>,
+                allowances: HashMap<(AccountId, AccountId), Balance>,
+                decimals: Option<u8>,
+                nonces: Option<HashMap<AccountId, u64>>,
+                wraps_native: bool,
+                transfer_fee_bps: u128,
+            }
+
+            thread_local! {
+                static TOKENS: RefCell<HashMap<AccountId, Token>> = RefCell::default();
+            }
+
+            fn with_token<R>(token: AccountId, f: impl FnOnce(&mut Token) -> R) -> R {
+                TOKENS.with(|tokens| f(tokens.borrow_mut().entry(token).or_default()))
+            }
+
+            pub fn set_balance(token: AccountId, owner: AccountId, value: Balance) {
+                with_token(token, |token| token.balances.insert(owner, value));
+            }
+
+            /// Makes `token` implement `decimals`; mocked tokens lack it by default.
+            pub fn set_decimals(token: AccountId, decimals: u8) {
+                with_token(token, |token| token.decimals = Some(decimals));
+            }
+
+            /// Makes `token` burn `fee_bps` basis points of every amount moved by
+            /// `transfer_from`.
+            pub fn set_transfer_fee(token: AccountId, fee_bps: u128) {
+                with_token(token, |token| token.transfer_fee_bps = fee_bps);
+            }
+
+            pub fn token_balance(token: &Erc20Ref, owner: AccountId) -> Balance {
+                balance_of(ink::ToAccountId::to_account_id(token), owner)
+            }
+
+            /// Makes `token` implement `deposit`, wrapping the native currency.
+            pub fn set_wraps_native(token: AccountId) {
+                with_token(token, |token| token.wraps_native = true);
+            }
+
+            pub fn balance_of(token: AccountId, owner: AccountId) -> Balance {
+                with_token(token, |token| {
+                    token.balances.get(&owner).copied().unwrap_or_default()
+                })
+            }
+
+            pub fn total_supply(token: &Erc20Ref) -> Balance {
+                with_token(ink::ToAccountId::to_account_id(token), |token| {
+                    token.balances.values().sum()
+                })
+            }
+
+            pub fn approve(token: AccountId, owner: AccountId, spender: AccountId, value: Balance) {
+                with_token(token, |token| {
+                    token.allowances.insert((owner, spender), value)
+                });
+            }
+
+            pub fn transfer_from(
+                token: &mut Erc20Ref,
+                from: AccountId,
+                to: AccountId,
+                value: Balance,
+            ) -> Result<(), erc20::Error> {
+                let spender = ink::env::account_id::<Env>();
+                with_token(ink::ToAccountId::to_account_id(token), |token| {
+                    let allowance = token
+                        .allowances
+                        .get(&(from, spender))
+                        .copied()
+                        .unwrap_or_default();
+                    if allowance < value {
+                        return Err(erc20::Error::InsufficientAllowance);
+                    }
+                    let from_balance = token.balances.get(&from).copied().unwrap_or_default();
+                    if from_balance < value {
+                        return Err(erc20::Error::InsufficientBalance);
+                    }
+                    token.allowances.insert((from, spender), allowance - value);
+                    token.balances.insert(from, from_balance - value);
+                    let burned = value * token.transfer_fee_bps / 10_000;
+                    *token.balances.entry(to).or_default() += value - burned;
+                    Ok(())
+                })
+            }
+
+            pub fn transfer(
+                token: &mut Erc20Ref,
+                to: AccountId,
+                value: Balance,
+            ) -> Result<(), erc20::Error> {
+                let from = ink::env::account_id::<Env>();
+                with_token(ink::ToAccountId::to_account_id(token), |token| {
+                    let from_balance = token.balances.get(&from).copied().unwrap_or_default();
+                    if from_balance < value {
+                        return Err(erc20::Error::InsufficientBalance);
+                    }
+                    token.balances.insert(from, from_balance - value);
+                    *token.balances.entry(to).or_default() += value;
+                    Ok(())
+                })
+            }
+
+            pub fn allowance(token: &Erc20Ref, owner: AccountId, spender: AccountId) -> Balance {
+                with_token(ink::ToAccountId::to_account_id(token), |token| {
+                    token
+                        .allowances
+                        .get(&(owner, spender))
+                        .copied()
+                        .unwrap_or_default()
+                })
+            }
+
+            pub fn decimals(token: &Erc20Ref) -> Option<u8> {
+                with_token(ink::ToAccountId::to_account_id(token), |token| {
+                    token.decimals
+                })
+            }
+
+            /// Makes `token` implement `nonces`, reporting `nonce` for `owner`.
+            pub fn set_nonce(token: AccountId, owner: AccountId, nonce: u64) {
+                with_token(token, |token| {
+                    token
+                        .nonces
+                        .get_or_insert_with(HashMap::new)
+                        .insert(owner, nonce)
+                });
+            }
+
+            pub fn nonces(token: &Erc20Ref, owner: AccountId) -> Option<u64> {
+                with_token(ink::ToAccountId::to_account_id(token), |token| {
+                    let nonces = token.nonces.as_ref()?;
+                    Some(nonces.get(&owner).copied().unwrap_or_default())
+                })
+            }
+
+            pub fn deposit(token: &Erc20Ref, value: Balance) -> Option<()> {
+                let to = ink::env::account_id::<Env>();
+                with_token(ink::ToAccountId::to_account_id(token), |token| {
+                    token.wraps_native.then(|| {
+                        *token.balances.entry(to).or_default() += value;
+                    })
+                })
+            }
+        }
+
+        /// In-memory stand-in for the `PriceOracle` contracts of the asset pairs.
+        pub mod oracle_mock {
+            use super::{AccountId, AssetId};
+            use std::{cell::RefCell, collections::HashMap};
+
+            thread_local! {
+                static RATES: RefCell<HashMap<(AccountId, AssetId), (u128, u128)>> =
+                    RefCell::default();
+                static UPDATED: RefCell<HashMap<(AccountId, AssetId), u64>> = RefCell::default();
+            }
+
+            pub fn set_rate(oracle: AccountId, asset_id: AssetId, rate: (u128, u128)) {
+                RATES.with(|rates| rates.borrow_mut().insert((oracle, asset_id), rate));
+            }
+
+            pub fn get_rate(oracle: AccountId, asset_id: AssetId) -> Option<(u128, u128)> {
+                RATES.with(|rates| rates.borrow().get(&(oracle, asset_id)).copied())
+            }
+
+            pub fn set_last_updated(oracle: AccountId, asset_id: AssetId, timestamp: u64) {
+                UPDATED.with(|updated| updated.borrow_mut().insert((oracle, asset_id), timestamp));
+            }
+
+            pub fn last_updated(oracle: AccountId, asset_id: AssetId) -> Option<u64> {
+                UPDATED.with(|updated| updated.borrow().get(&(oracle, asset_id)).copied())
+            }
+        }
+
+        /// In-memory stand-in for the `SwapReceiver` contracts swaps pay out to.
+        pub mod receiver_mock {
+            use super::{AccountId, AssetId, Balance};
+            use std::{cell::RefCell, collections::HashMap};
+
+            thread_local! {
+                static RECEIVED: RefCell<HashMap<AccountId, Vec<(AssetId, Balance)>>> =
+                    RefCell::default();
+            }
+
+            /// Makes `receiver` implement `on_swap_received`.
+            pub fn register(receiver: AccountId) {
+                RECEIVED.with(|received| {
+                    received.borrow_mut().entry(receiver).or_default();
+                });
+            }
+
+            /// Returns the callbacks `receiver` handled, oldest first.
+            pub fn received(receiver: AccountId) -> Vec<(AssetId, Balance)> {
+                RECEIVED.with(|received| {
+                    received
+                        .borrow()
+                        .get(&receiver)
+                        .cloned()
+                        .unwrap_or_default()
+                })
+            }
+
+            pub fn on_swap_received(
+                receiver: AccountId,
+                asset_id: AssetId,
+                amount: Balance,
+            ) -> Option<()> {
+                RECEIVED.with(|received| {
+                    received
+                        .borrow_mut()
+                        .get_mut(&receiver)
+                        .map(|calls| calls.push((asset_id, amount)))
+                })
+            }
+        }
+
+        /// In-memory stand-in for the PSP22 chain extension.
+        pub mod psp22_mock {
+            use super::{AccountId, AssetId, Balance};
+            use crate::extension_ids::*;
+            use scale::{Decode, Encode};
+            use std::{
+                cell::RefCell,
+                collections::{HashMap, HashSet},
+            };
+
+            /// Status code reported by the mocked runtime when a call fails.
+            const FAILED: u32 = 1;
+
+            #[derive(Default)]
+            struct Ledger {
+                balances: HashMap<(AssetId, AccountId), Balance>,
+                allowances: HashMap<(AssetId, AccountId, AccountId), Balance>,
+                metadata: HashMap<AssetId, (Vec<u8>, Vec<u8>, u8)>,
+                frozen: HashSet<AssetId>,
+                approvals: Vec<(AssetId, AccountId, Balance)>,
+            }
+
+            thread_local! {
+                static LEDGER: RefCell<Ledger> = RefCell::default();
+            }
+
+            fn with_ledger<R>(f: impl FnOnce(&mut Ledger) -> R) -> R {
+                LEDGER.with(|ledger| f(&mut ledger.borrow_mut()))
+            }
+
+            pub fn set_balance(asset_id: AssetId, owner: AccountId, value: Balance) {
+                with_ledger(|ledger| ledger.balances.insert((asset_id, owner), value));
+            }
+
+            pub fn balance_of(asset_id: AssetId, owner: AccountId) -> Balance {
+                with_ledger(|ledger| {
+                    ledger
+                        .balances
+                        .get(&(asset_id, owner))
+                        .copied()
+                        .unwrap_or_default()
+                })
+            }
+
+            pub fn set_allowance(
+                asset_id: AssetId,
+                owner: AccountId,
+                spender: AccountId,
+                value: Balance,
+            ) {
+                with_ledger(|ledger| ledger.allowances.insert((asset_id, owner, spender), value));
+            }
+
+            pub fn allowance(asset_id: AssetId, owner: AccountId, spender: AccountId) -> Balance {
+                with_ledger(|ledger| {
+                    ledger
+                        .allowances
+                        .get(&(asset_id, owner, spender))
+                        .copied()
+                        .unwrap_or_default()
+                })
+            }
+
+            pub fn set_metadata(asset_id: AssetId, name: &[u8], symbol: &[u8], decimals: u8) {
+                with_ledger(|ledger| {
+                    ledger
+                        .metadata
+                        .insert(asset_id, (name.to_vec(), symbol.to_vec(), decimals))
+                });
+            }
+
+            /// Makes approvals of the asset fail.
+            pub fn freeze(asset_id: AssetId) {
+                with_ledger(|ledger| ledger.frozen.insert(asset_id));
+            }
+
+            /// Returns every successful approval `(asset_id, spender, value)`, oldest
+            /// first.
+            pub fn approvals() -> Vec<(AssetId, AccountId, Balance)> {
+                with_ledger(|ledger| ledger.approvals.clone())
+            }
+
+            fn is_frozen(asset_id: AssetId) -> bool {
+                with_ledger(|ledger| ledger.frozen.contains(&asset_id))
+            }
+
+            fn total_supply(asset_id: AssetId) -> Balance {
+                with_ledger(|ledger| {
+                    ledger
+                        .balances
+                        .iter()
+                        .filter(|((asset, _), _)| *asset == asset_id)
+                        .map(|(_, value)| value)
+                        .sum()
+                })
+            }
+
+            fn transfer(asset_id: AssetId, from: AccountId, to: AccountId, value: Balance) -> u32 {
+                let from_balance = balance_of(asset_id, from);
+                if from_balance < value {
+                    return FAILED;
+                }
+                set_balance(asset_id, from, from_balance - value);
+                set_balance(asset_id, to, balance_of(asset_id, to) + value);
+                0
+            }
+
+            /// A single mocked extension method, executed on behalf of `contract`.
+            struct Method {
+                func_id: u32,
+                contract: AccountId,
+            }
+
+            impl Method {
+                fn dispatch(&self, mut input: &[u8], output: &mut Vec<u8>) -> u32 {
+                    let contract = self.contract;
+                    match self.func_id {
+                        TOKEN_NAME | TOKEN_SYMBOL | TOKEN_DECIMALS => {
+                            let asset_id: AssetId = Decode::decode(&mut input).unwrap();
+                            let Some((name, symbol, decimals)) =
+                                with_ledger(|ledger| ledger.metadata.get(&asset_id).cloned())
+                            else {
+                                return FAILED;
+                            };
+                            match self.func_id {
+                                TOKEN_NAME => name.encode_to(output),
+                                TOKEN_SYMBOL => symbol.encode_to(output),
+                                _ => decimals.encode_to(output),
+                            }
+                            0
+                        }
+                        TOTAL_SUPPLY => {
+                            let asset_id = Decode::decode(&mut input).unwrap();
+                            total_supply(asset_id).encode_to(output);
+                            0
+                        }
+                        BALANCE_OF => {
+                            let (asset_id, owner) = Decode::decode(&mut input).unwrap();
+                            balance_of(asset_id, owner).encode_to(output);
+                            0
+                        }
+                        ALLOWANCE => {
+                            let (asset_id, owner, spender) = Decode::decode(&mut input).unwrap();
+                            allowance(asset_id, owner, spender).encode_to(output);
+                            0
+                        }
+                        TRANSFER => {
+                            let (asset_id, to, value) = Decode::decode(&mut input).unwrap();
+                            transfer(asset_id, contract, to, value)
+                        }
+                        TRANSFER_FROM => {
+                            let (asset_id, from, to, value) = Decode::decode(&mut input).unwrap();
+                            let allowance = allowance(asset_id, from, contract);
+                            if allowance < value {
+                                return FAILED;
+                            }
+                            let status = transfer(asset_id, from, to, value);
+                            if status == 0 {
+                                set_allowance(asset_id, from, contract, allowance - value);
+                            }
+                            status
+                        }
+                        APPROVE => {
+                            let (asset_id, spender, value) = Decode::decode(&mut input).unwrap();
+                            if is_frozen(asset_id) {
+                                return FAILED;
+                            }
+                            set_allowance(asset_id, contract, spender, value);
+                            with_ledger(|ledger| ledger.approvals.push((asset_id, spender, value)));
+                            0
+                        }
+                        INCREASE_ALLOWANCE | DECREASE_ALLOWANCE => {
+                            let (asset_id, spender, value): (AssetId, AccountId, Balance) =
+                                Decode::decode(&mut input).unwrap();
+                            let current = allowance(asset_id, contract, spender);
+                            let updated = if self.func_id == INCREASE_ALLOWANCE {
+                                current.checked_add(value)
+                            } else {
+                                current.checked_sub(value)
+                            };
+                            let Some(updated) = updated else {
+                                return FAILED;
+                            };
+                            set_allowance(asset_id, contract, spender, updated);
+                            0
+                        }
+                        _ => unreachable!("unregistered extension method"),
+                    }
+                }
+            }
+
+            impl ink::env::test::ChainExtension for Method {
+                fn func_id(&self) -> u32 {
+                    self.func_id
+                }
+
+                fn call(&mut self, input: &[u8], output: &mut Vec<u8>) -> u32 {
+                    // The off-chain engine hands over the encoded arguments as a
+                    // length-prefixed byte vector.
+                    let input = Vec::<u8>::decode(&mut &input[..]).unwrap();
+                    self.dispatch(&input, output)
+                }
+            }
+
+            /// Registers the mocked extension methods for the contract at `contract`.
+            pub fn register(contract: AccountId) {
+                for func_id in ALL {
+                    ink::env::test::register_chain_extension(Method { func_id, contract });
+                }
+            }
+        }
+
+        type Event = <Psp22Extension as ::ink::reflect::ContractEventBase>::Type;
+
+        const ERC20: [u8; 32] = [0x10; 32];
+
+        fn erc20_at(account: AccountId) -> Erc20Ref {
+            Erc20Ref::from_account_id(account)
+        }
+
+        fn recorded_events() -> Vec<Event> {
+            ink::env::test::recorded_events()
+                .map(|event| {
+                    <Event as scale::Decode>::decode(&mut &event.data[..])
+                        .expect("encountered invalid contract event data buffer")
+                })
+                .collect()
+        }
+
+        /// The address `setup` deploys the contract at, i.e. what
+        /// `self.env().account_id()` returns inside the contract.
+        fn contract_id() -> AccountId {
+            AccountId::from([0xC0; 32])
+        }
+
+        /// Gives the contract `amount` of the native currency.
+        fn fund_native(amount: Balance) {
+            set_account_balance::<Env>(contract_id(), amount);
+        }
+
+        /// Returns the backing ERC20 held by the contract.
+        fn contract_erc20_balance() -> Balance {
+            erc20_mock::balance_of(AccountId::from(ERC20), contract_id())
+        }
+
+        /// Deploys the contract at a fixed address with a pair registered for asset `1`,
+        /// backed by an ERC20 of which Alice holds and has approved `1_000`. The contract
+        /// holds `1_000` of the asset to pay swaps out of.
+        fn setup() -> (Psp22Extension, AccountId) {
+            let alice = default_accounts::<Env>().alice;
+            let contract_id = contract_id();
+            set_callee::<Env>(contract_id);
+            psp22_mock::register(contract_id);
+            psp22_mock::set_balance(1, contract_id, 1_000);
+
+            let mut contract = Psp22Extension::new();
+            assert_eq!(
+                contract.create_asset_pair(1, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            erc20_mock::set_balance(AccountId::from(ERC20), alice, 1_000);
+            erc20_mock::approve(AccountId::from(ERC20), alice, contract_id, 1_000);
+            (contract, contract_id)
+        }
+
+        #[ink::test]
+        fn all_pairs_works() {
+            psp22_mock::register(ink::env::account_id::<Env>());
+            let mut contract = Psp22Extension::new();
+            assert_eq!(contract.all_pairs(), Vec::new());
+
+            assert_eq!(
+                contract.create_asset_pair(1, erc20_at(AccountId::from([0x10; 32]))),
+                Ok(())
+            );
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from([0x20; 32]))),
+                Ok(())
+            );
+            // Re-registering a pair updates it in place.
+            assert_eq!(
+                contract.create_asset_pair(1, erc20_at(AccountId::from([0x11; 32]))),
+                Ok(())
+            );
+
+            assert_eq!(
+                contract.all_pairs(),
+                vec![
+                    (
+                        1,
+                        PairInfo {
+                            erc20: AccountId::from([0x11; 32]),
+                            config: PairConfig::default(),
+                        }
+                    ),
+                    (
+                        2,
+                        PairInfo {
+                            erc20: AccountId::from([0x20; 32]),
+                            config: PairConfig::default(),
+                        }
+                    ),
+                ]
+            );
+        }
+
+        #[ink::test]
+        fn swap_and_transfer_native_works() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            fund_native(500);
+            let alice_native = get_account_balance::<Env>(alice).unwrap();
+
+            // Refused until the pair is flagged as wrapping the native currency.
+            assert_eq!(
+                contract.swap_and_transfer_native(1, 100),
+                Err(Psp22Error::NotNativeWrapper)
+            );
+            assert_eq!(contract.set_native_wrapper(1, true), Ok(()));
+
+            assert_eq!(contract.swap_and_transfer_native(1, 100), Ok(100));
+            assert_eq!(get_account_balance::<Env>(alice), Ok(alice_native + 100));
+            assert_eq!(get_account_balance::<Env>(contract_id), Ok(400));
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 900);
+            assert_eq!(contract_erc20_balance(), 100);
+        }
+
+        #[ink::test]
+        fn swap_and_transfer_native_respects_reserve() {
+            let (mut contract, contract_id) = setup();
+            fund_native(500);
+            assert_eq!(contract.set_native_wrapper(1, true), Ok(()));
+            assert_eq!(contract.set_native_reserve(400), Ok(()));
+
+            assert_eq!(contract.swap_and_transfer_native(1, 100), Ok(100));
+            assert_eq!(get_account_balance::<Env>(contract_id), Ok(400));
+            assert_eq!(
+                contract.swap_and_transfer_native(1, 1),
+                Err(Psp22Error::ReserveViolation)
+            );
+            assert_eq!(get_account_balance::<Env>(contract_id), Ok(400));
+        }
+
+        #[ink::test]
+        fn set_native_wrapper_requires_owner() {
+            let (mut contract, _) = setup();
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(
+                contract.set_native_wrapper(1, true),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn swap_for_asset_works() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(psp22_mock::balance_of(1, alice), 100);
+            assert_eq!(psp22_mock::balance_of(1, contract_id), 900);
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 900);
+
+            assert_eq!(
+                contract.swap_for_asset(2, 100),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn attempt_swap_for_asset_records_failure() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            let events_before = recorded_events().len();
+
+            // Pulling more ERC20 than approved fails before anything moves.
+            assert_eq!(contract.attempt_swap_for_asset(1, 2_000), None);
+            // The contract cannot pay out more of the asset than it holds, so the
+            // pulled ERC20 is handed back.
+            erc20_mock::approve(AccountId::from(ERC20), alice, contract_id, 2_000);
+            erc20_mock::set_balance(AccountId::from(ERC20), alice, 2_000);
+            assert_eq!(contract.attempt_swap_for_asset(1, 1_500), None);
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 2_000);
+
+            let events = recorded_events();
+            assert_eq!(events.len(), events_before + 2);
+            let reasons = events[events_before..]
+                .iter()
+                .map(|event| match event {
+                    Event::SwapFailed(SwapFailed {
+                        caller,
+                        asset_id,
+                        direction,
+                        reason,
+                        ..
+                    }) => {
+                        assert_eq!(*caller, alice);
+                        assert_eq!(*asset_id, 1);
+                        assert_eq!(*direction, SwapDirection::ToAsset);
+                        *reason
+                    }
+                    _ => panic!("expected only SwapFailed events"),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                reasons,
+                vec![
+                    Psp22Error::InsufficientAllowance.discriminant(),
+                    Psp22Error::TotalSupplyFailed.discriminant(),
+                ]
+            );
+
+            assert_eq!(contract.attempt_swap_for_asset(1, 100), Some(100));
+        }
+
+        #[ink::test]
+        fn allowlist_works() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+
+            // Anyone may swap while the allowlist is disabled.
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+
+            assert_eq!(contract.set_allowlist_enabled(true), Ok(()));
+            assert_eq!(
+                contract.swap_for_asset(1, 10),
+                Err(Psp22Error::NotAllowlisted)
+            );
+            assert_eq!(contract.set_allowlisted(accounts.alice, true), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+            assert_eq!(contract.set_allowlisted(accounts.alice, false), Ok(()));
+            assert_eq!(
+                contract.swap_for_asset(1, 10),
+                Err(Psp22Error::NotAllowlisted)
+            );
+
+            assert_eq!(contract.set_allowlist_enabled(false), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.set_allowlisted(accounts.bob, true),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(
+                contract.set_allowlist_enabled(true),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn blocklist_works() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+
+            assert_eq!(contract.set_blocked(accounts.alice, true), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::Blocked));
+            // Blocking wins over the allowlist.
+            assert_eq!(contract.set_allowlist_enabled(true), Ok(()));
+            assert_eq!(contract.set_allowlisted(accounts.alice, true), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::Blocked));
+            // Nothing was pulled from the blocked account.
+            assert_eq!(contract_erc20_balance(), 0);
+
+            assert_eq!(contract.set_blocked(accounts.alice, false), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.set_blocked(accounts.alice, true),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn pair_config_round_trips() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.pair_configs.get(1), None);
+            assert_eq!(contract.pair_config_of(1), PairConfig::default());
+
+            assert_eq!(contract.set_native_wrapper(1, true), Ok(()));
+            let expected = PairConfig {
+                is_native_wrapper: true,
+                config_version: 1,
+                ..Default::default()
+            };
+            assert_eq!(contract.pair_configs.get(1), Some(expected.clone()));
+            assert_eq!(contract.all_pairs()[0].1.config, expected);
+
+            assert_eq!(
+                contract.set_native_wrapper(2, true),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            assert_eq!(contract.pair_configs.get(2), None);
+        }
+
+        #[ink::test]
+        fn swap_for_asset_partial_works() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            let capped = PairConfig {
+                max_swap: Some(300),
+                ..Default::default()
+            };
+            assert_eq!(contract.set_pair_config(1, capped.clone()), Ok(()));
+
+            assert_eq!(
+                contract.swap_for_asset(1, 500),
+                Err(Psp22Error::AboveMaxSwap)
+            );
+            assert_eq!(
+                contract.swap_for_asset_partial(1, 500),
+                Err(Psp22Error::PartialFillNotAllowed)
+            );
+
+            let partial = PairConfig {
+                allow_partial: true,
+                ..capped
+            };
+            assert_eq!(contract.set_pair_config(1, partial), Ok(()));
+            assert_eq!(contract.swap_for_asset_partial(1, 500), Ok((300, 300)));
+            // The unfilled 200 never left Alice's account.
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 700);
+            assert_eq!(contract_erc20_balance(), 300);
+            assert_eq!(psp22_mock::balance_of(1, alice), 300);
+
+            // Amounts within the cap are filled in full.
+            assert_eq!(contract.swap_for_asset_partial(1, 100), Ok((100, 100)));
+        }
+
+        #[ink::test]
+        fn status_code_mappings_are_consistent() {
+            use ink::env::chain_extension::FromStatusCode;
+
+            assert_eq!(Psp22Error::from_status_code(0), Ok(()));
+            for (code, error) in crate::STATUS_CODES {
+                assert_eq!(Psp22Error::from_status_code(code), Err(error.clone()));
+                assert_eq!(crate::status_code_for(error.discriminant()), code);
+            }
+            assert_eq!(
+                crate::status_code_for(Psp22Error::TotalSupplyFailed.discriminant()),
+                1
+            );
+            // Errors raised by the contract itself have no status code.
+            assert_eq!(
+                crate::status_code_for(Psp22Error::NotOwner.discriminant()),
+                0
+            );
+        }
+
+        #[ink::test]
+        fn erc20_decimals_override_works() {
+            let (mut contract, _) = setup();
+
+            // The mocked ERC20 does not implement `decimals`.
+            assert_eq!(
+                contract.erc20_decimals(1),
+                Err(Psp22Error::Erc20DecimalsUnknown)
+            );
+            let config = PairConfig {
+                erc20_decimals_override: Some(6),
+                ..Default::default()
+            };
+            assert_eq!(contract.set_pair_config(1, config), Ok(()));
+            assert_eq!(contract.erc20_decimals(1), Ok(6));
+
+            // A live `decimals` takes precedence over the override.
+            erc20_mock::set_decimals(AccountId::from(ERC20), 18);
+            assert_eq!(contract.erc20_decimals(1), Ok(18));
+
+            assert_eq!(
+                contract.erc20_decimals(2),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn would_round_to_zero_works() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.would_round_to_zero(1, 1), Ok(false));
+
+            let config = PairConfig {
+                rate_numerator: 1,
+                rate_denominator: 1_000,
+                ..Default::default()
+            };
+            assert_eq!(contract.set_pair_config(1, config), Ok(()));
+            assert_eq!(contract.would_round_to_zero(1, 999), Ok(true));
+            assert_eq!(contract.would_round_to_zero(1, 1_000), Ok(false));
+            assert_eq!(contract.swap_for_asset(1, 1_000), Ok(1));
+
+            assert_eq!(
+                contract.would_round_to_zero(2, 1_000),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            let invalid = PairConfig {
+                rate_denominator: 0,
+                ..Default::default()
+            };
+            assert_eq!(
+                contract.set_pair_config(1, invalid),
+                Err(Psp22Error::InvalidRate)
+            );
+        }
+
+        #[ink::test]
+        fn zero_output_swap_is_rejected() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            let config = PairConfig {
+                rate_numerator: 1,
+                rate_denominator: 1_000,
+                ..Default::default()
+            };
+            assert_eq!(contract.set_pair_config(1, config), Ok(()));
+
+            assert_eq!(contract.swap_for_asset(1, 999), Err(Psp22Error::ZeroOutput));
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 1_000);
+            assert_eq!(contract_erc20_balance(), 0);
+        }
+
+        #[ink::test]
+        fn extension_ids_match_extension_methods() {
+            // Every mocked method is registered under its `extension_ids` constant, so a
+            // call through an attribute with a different id would find no handler.
+            let (mut contract, contract_id) = setup();
+            let bob = default_accounts::<Env>().bob;
+            let alice = default_accounts::<Env>().alice;
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 12);
+            psp22_mock::set_balance(1, alice, 10);
+            psp22_mock::set_allowance(1, alice, contract_id, 10);
+
+            assert_eq!(contract.token_name(1), Ok(b"Asset".to_vec()));
+            assert_eq!(contract.token_symbol(1), Ok(b"AST".to_vec()));
+            assert_eq!(contract.token_decimals(1), Ok(12));
+            assert_eq!(contract.total_supply(1), Ok(1_010));
+            assert_eq!(contract.balance_of(1, contract_id), Ok(1_000));
+            assert_eq!(contract.transfer(1, bob, 10), Ok(()));
+            assert_eq!(contract.transfer_from(1, alice, contract_id, 10), Ok(()));
+            assert_eq!(contract.approve(1, bob, 5), Ok(()));
+            assert_eq!(contract.increase_allowance(1, bob, 3), Ok(()));
+            assert_eq!(contract.decrease_allowance(1, bob, 1), Ok(()));
+            assert_eq!(contract.allowance(1, contract_id, bob), Ok(7));
+        }
+
+        #[ink::test]
+        fn moving_contract_holdings_is_owner_only() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(contract.approve(1, accounts.charlie, 10), Ok(()));
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.transfer(1, accounts.bob, 100),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(
+                contract.approve(1, accounts.bob, Balance::MAX),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(
+                contract.increase_allowance(1, accounts.bob, 100),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(
+                contract.decrease_allowance(1, accounts.charlie, 10),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(
+                contract.withdraw_liquidity(1, 100, accounts.bob),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(psp22_mock::balance_of(1, contract_id), 900);
+            assert_eq!(psp22_mock::allowance(1, contract_id, accounts.bob), 0);
+            assert_eq!(psp22_mock::allowance(1, contract_id, accounts.charlie), 10);
+            assert_eq!(contract.escrowed_of(1), 100);
+        }
+
+        #[ink::test]
+        fn transfer_from_spends_only_the_callers_allowance() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            psp22_mock::set_balance(1, accounts.bob, 100);
+            psp22_mock::set_allowance(1, accounts.bob, contract_id, 100);
+
+            // Neither another holder nor the owner may spend bob's allowance.
+            for caller in [accounts.charlie, accounts.alice] {
+                ink::env::test::set_caller::<Env>(caller);
+                assert_eq!(
+                    contract.transfer_from(1, accounts.bob, caller, 100),
+                    Err(Psp22Error::NotAllowanceHolder)
+                );
+            }
+            assert_eq!(psp22_mock::allowance(1, accounts.bob, contract_id), 100);
+            assert_eq!(psp22_mock::balance_of(1, accounts.bob), 100);
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.transfer_from(1, accounts.bob, accounts.charlie, 40),
+                Ok(())
+            );
+            assert_eq!(psp22_mock::balance_of(1, accounts.charlie), 40);
+        }
+
+        #[ink::test]
+        fn safe_approve_resets_allowance_first() {
+            let (mut contract, contract_id) = setup();
+            let bob = default_accounts::<Env>().bob;
+            assert_eq!(contract.approve(1, bob, 5), Ok(()));
+            let approvals_before = psp22_mock::approvals().len();
+            assert_eq!(contract.safe_approve(1, bob, 9), Ok(()));
+            assert_eq!(psp22_mock::allowance(1, contract_id, bob), 9);
+            // The reset to zero reaches the extension before the new value does.
+            assert_eq!(
+                psp22_mock::approvals()[approvals_before..],
+                [(1, bob, 0), (1, bob, 9)]
+            );
+
+            ink::env::test::set_caller::<Env>(bob);
+            assert_eq!(
+                contract.safe_approve(1, bob, Balance::MAX),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(psp22_mock::approvals().len(), approvals_before + 2);
+            assert_eq!(psp22_mock::allowance(1, contract_id, bob), 9);
+        }
+
+        #[ink::test]
+        fn top_up_allowance_returns_new_allowance() {
+            let (mut contract, contract_id) = setup();
+            let bob = default_accounts::<Env>().bob;
+            psp22_mock::set_allowance(1, contract_id, bob, 5);
+            assert_eq!(contract.top_up_allowance(1, bob, 7), Ok(12));
+            assert_eq!(psp22_mock::allowance(1, contract_id, bob), 12);
+            assert_eq!(contract.top_up_allowance(1, bob, 0), Ok(12));
+
+            ink::env::test::set_caller::<Env>(bob);
+            assert_eq!(
+                contract.top_up_allowance(1, bob, 1_000),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(psp22_mock::allowance(1, contract_id, bob), 12);
+        }
+
+        #[ink::test]
+        fn token_name_string_decodes_utf8() {
+            let (contract, _) = setup();
+            psp22_mock::set_metadata(1, "Ässet".as_bytes(), b"AST", 12);
+            assert_eq!(contract.token_name_string(1), Ok(String::from("Ässet")));
+
+            psp22_mock::set_metadata(2, &[0xff, 0xfe], b"BAD", 12);
+            assert_eq!(contract.token_name_string(2), Err(Psp22Error::InvalidUtf8));
+        }
+
+        #[ink::test]
+        fn recent_swaps_evicts_oldest() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(contract.recent_swaps(alice), Vec::new());
+
+            for amount in 1..=MAX_RECENT_SWAPS as Balance + 3 {
+                assert_eq!(contract.swap_for_asset(1, amount), Ok(amount));
+            }
+
+            let amounts = contract
+                .recent_swaps(alice)
+                .iter()
+                .map(|receipt| receipt.amount_in)
+                .collect::<Vec<_>>();
+            assert_eq!(amounts, (4..=13).collect::<Vec<Balance>>());
+            assert_eq!(
+                contract.recent_swaps(alice)[0],
+                SwapReceipt {
+                    asset_id: 1,
+                    direction: SwapDirection::ToAsset,
+                    amount_in: 4,
+                    amount_out: 4,
+                    timestamp: 0,
+                }
+            );
+            assert_eq!(
+                contract.recent_swaps(default_accounts::<Env>().bob),
+                Vec::new()
+            );
+        }
+
+        #[ink::test]
+        fn swap_or_create_registers_from_template() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            let template = PairConfig {
+                rate_numerator: 2,
+                rate_denominator: 1,
+                max_swap: Some(500),
+                ..Default::default()
+            };
+            assert_eq!(contract.set_pair_template(template.clone()), Ok(()));
+            psp22_mock::set_balance(2, contract_id, 1_000);
+
+            // Non-owners cannot register pairs this way.
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.swap_or_create(2, erc20_at(AccountId::from(ERC20)), 100),
+                Err(Psp22Error::NotOwner)
+            );
+            assert!(!contract.asset_pairs.contains(2));
+
+            ink::env::test::set_caller::<Env>(accounts.alice);
+            assert_eq!(
+                contract.swap_or_create(2, erc20_at(AccountId::from(ERC20)), 100),
+                Ok(200)
+            );
+            assert_eq!(
+                contract.all_pairs()[1],
+                (
+                    2,
+                    PairInfo {
+                        erc20: AccountId::from(ERC20),
+                        config: PairConfig {
+                            config_version: 1,
+                            ..template
+                        },
+                    }
+                )
+            );
+            assert_eq!(psp22_mock::balance_of(2, accounts.alice), 200);
+
+            // Once registered, the pair swaps like any other.
+            assert_eq!(
+                contract.swap_or_create(2, erc20_at(AccountId::from(ERC20)), 600),
+                Err(Psp22Error::AboveMaxSwap)
+            );
+        }
+
+        #[ink::test]
+        fn accrued_fees_of_works() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.set_fee(1, 250), Ok(()));
+            assert_eq!(contract.set_fee(1, 10_001), Err(Psp22Error::InvalidFee));
+            assert_eq!(contract.accrued_fees_of(1), 0);
+
+            // 2.5% of each swap's output is retained.
+            assert_eq!(contract.swap_for_asset(1, 200), Ok(195));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(98));
+            assert_eq!(contract.accrued_fees_of(1), 7);
+            assert_eq!(psp22_mock::balance_of(1, contract_id), 707);
+
+            assert_eq!(contract.set_fee_recipient(accounts.charlie), Ok(()));
+            assert_eq!(contract.withdraw_fees(1), Ok(7));
+            assert_eq!(contract.accrued_fees_of(1), 0);
+            assert_eq!(psp22_mock::balance_of(1, accounts.charlie), 7);
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.set_fee(1, 0), Err(Psp22Error::NotOwner));
+            assert_eq!(contract.withdraw_fees(1), Err(Psp22Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn selector_map_lists_psp22_selectors() {
+            let (contract, _) = setup();
+            let map = contract.selector_map();
+            assert!(map.contains(&(vec![0xdb, 0x20, 0xf9, 0xf5], crate::extension_ids::TRANSFER)));
+            assert!(map.contains(&(
+                vec![0x65, 0x68, 0x38, 0x2f],
+                crate::extension_ids::BALANCE_OF
+            )));
+            assert_eq!(
+                map.iter().map(|(_, func_id)| *func_id).collect::<Vec<_>>(),
+                crate::extension_ids::ALL
+            );
+            // Each selector starts with the id of the extension method it forwards to.
+            for (selector, func_id) in map {
+                assert_eq!(
+                    u32::from(u16::from_be_bytes([selector[0], selector[1]])),
+                    func_id
+                );
+            }
+        }
+
+        #[ink::test]
+        fn permit_nonce_forwards_to_erc20() {
+            let (contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(
+                contract.permit_nonce(1, accounts.alice),
+                Err(Psp22Error::PermitUnsupported)
+            );
+            erc20_mock::set_nonce(AccountId::from(ERC20), accounts.alice, 4);
+            assert_eq!(contract.permit_nonce(1, accounts.alice), Ok(4));
+            assert_eq!(contract.permit_nonce(1, accounts.bob), Ok(0));
+            assert_eq!(
+                contract.permit_nonce(2, accounts.alice),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn backing_total_supply_works() {
+            let (contract, contract_id) = setup();
+            erc20_mock::set_balance(AccountId::from(ERC20), contract_id, 250);
+            assert_eq!(contract.backing_total_supply(1), Ok(1_250));
+            assert_eq!(
+                contract.backing_total_supply(2),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn collateral_ratio_works() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            psp22_mock::set_balance(1, alice, 500);
+            assert_eq!(contract.swap_for_asset(1, 200), Ok(200));
+            assert_eq!(contract.collateral_ratio(1), Ok((200, 1_500)));
+            assert_eq!(
+                contract.collateral_ratio(2),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn snapshot_supplies_covers_every_asset() {
+            let (mut contract, contract_id) = setup();
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            psp22_mock::set_balance(2, contract_id, 250);
+            psp22_mock::set_balance(2, default_accounts::<Env>().bob, 50);
+            assert_eq!(contract.snapshot_supplies(), Ok(vec![(1, 1_000), (2, 300)]));
+        }
+
+        #[ink::test]
+        fn sync_escrow_resets_drifted_escrow() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(contract.escrowed_of(1), 100);
+
+            // Asset `2` shares the ERC20, so 30 of the 160 held are escrowed for it.
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            psp22_mock::set_balance(2, contract_id, 1_000);
+            assert_eq!(contract.swap_for_asset(2, 30), Ok(30));
+            erc20_mock::set_balance(AccountId::from(ERC20), contract_id, 160);
+
+            assert_eq!(contract.sync_escrow(1), Err(Psp22Error::NotPaused));
+            assert_eq!(contract.set_paused(true), Ok(()));
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.sync_escrow(1), Err(Psp22Error::NotOwner));
+            ink::env::test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.sync_escrow(3), Err(Psp22Error::AssetPairNotFound));
+
+            assert_eq!(contract.sync_escrow(1), Ok(()));
+            assert_eq!(contract.escrowed_of(1), 130);
+            assert_eq!(contract.escrowed_of(2), 30);
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::EscrowSynced(EscrowSynced {
+                    asset_id: 1,
+                    old: 100,
+                    new: 130,
+                    ..
+                }))
+            ));
+        }
+
+        #[ink::test]
+        fn escrow_changed_events_track_escrow() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.set_native_wrapper(1, true), Ok(()));
+            fund_native(1_000);
+            assert_eq!(
+                contract.set_pair_config(
+                    1,
+                    PairConfig {
+                        allow_partial: true,
+                        max_swap: Some(50),
+                        is_native_wrapper: true,
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+
+            let mut expected_total = 0;
+            type Operation = fn(&mut Psp22Extension) -> bool;
+            let operations: [(Balance, Operation); 4] = [
+                (30, |contract| contract.swap_for_asset(1, 30).is_ok()),
+                (80, |contract| {
+                    contract.swap_for_asset_partial(1, 80).is_ok()
+                }),
+                (20, |contract| {
+                    contract.attempt_swap_for_asset(1, 20).is_some()
+                }),
+                (10, |contract| {
+                    contract.swap_and_transfer_native(1, 10).is_ok()
+                }),
+            ];
+            for (requested, operation) in operations {
+                assert!(operation(&mut contract));
+                let amount = requested.min(50);
+                expected_total += amount;
+                let escrow_event = recorded_events()
+                    .into_iter()
+                    .rev()
+                    .find_map(|event| match event {
+                        Event::EscrowChanged(event) => Some(event),
+                        _ => None,
+                    })
+                    .expect("an EscrowChanged event was emitted");
+                assert_eq!(escrow_event.asset_id, 1);
+                assert_eq!(escrow_event.direction, SwapDirection::ToAsset);
+                assert_eq!(escrow_event.amount, amount);
+                assert_eq!(escrow_event.new_total, expected_total);
+                assert_eq!(contract.escrowed_of(1), escrow_event.new_total);
+            }
+            assert_eq!(contract_erc20_balance(), contract.escrowed_of(1));
+        }
+
+        #[ink::test]
+        fn admin_messages_require_owner() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.set_pair_config(1, PairConfig::default()),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(
+                contract.set_fee_recipient(accounts.bob),
+                Err(Psp22Error::NotOwner)
+            );
+
+            ink::env::test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.set_fee_recipient(accounts.bob), Ok(()));
+        }
+
+        #[ink::test]
+        fn pauser_can_pause_but_not_configure() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.set_pauser(accounts.bob), Ok(()));
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.set_paused(true), Ok(()));
+            assert!(contract.is_paused());
+            assert_eq!(contract.set_fee(1, 100), Err(Psp22Error::NotOwner));
+            assert_eq!(contract.set_pauser(accounts.bob), Err(Psp22Error::NotOwner));
+
+            ink::env::test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::Paused));
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.set_paused(false), Ok(()));
+            ink::env::test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+
+            ink::env::test::set_caller::<Env>(accounts.charlie);
+            assert_eq!(contract.set_paused(true), Err(Psp22Error::NotPauser));
+        }
+
+        #[ink::test]
+        fn swap_for_asset_with_checks_backing() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(
+                contract.swap_for_asset_with(1, AccountId::from([0x11; 32]), 10),
+                Err(Psp22Error::UnknownBacking)
+            );
+            assert_eq!(
+                contract.swap_for_asset_with(1, AccountId::from(ERC20), 10),
+                Ok(10)
+            );
+            assert_eq!(psp22_mock::balance_of(1, accounts.alice), 10);
+            assert_eq!(
+                erc20_mock::balance_of(AccountId::from(ERC20), accounts.alice),
+                990
+            );
+        }
+
+        #[ink::test]
+        fn swap_to_erc20_min_works() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(
+                contract.set_pair_config(
+                    1,
+                    PairConfig {
+                        rate_numerator: 2,
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                contract.swap_for_asset_min(1, 100, 201),
+                Err(Psp22Error::SlippageExceeded)
+            );
+            assert_eq!(contract.swap_for_asset_min(1, 100, 200), Ok(200));
+
+            psp22_mock::set_allowance(1, alice, contract_id, 200);
+            assert_eq!(
+                contract.swap_to_erc20_min(1, 100, 51),
+                Err(Psp22Error::SlippageExceeded)
+            );
+            assert_eq!(contract.swap_to_erc20_min(1, 100, 50), Ok(50));
+            assert_eq!(psp22_mock::balance_of(1, alice), 100);
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 950);
+            assert_eq!(contract.escrowed_of(1), 50);
+            assert_eq!(contract_erc20_balance(), 50);
+            assert_eq!(
+                contract
+                    .recent_swaps(alice)
+                    .last()
+                    .map(|receipt| receipt.direction),
+                Some(SwapDirection::ToErc20)
+            );
+
+            // Only escrowed ERC20 is paid out.
+            assert_eq!(
+                contract.swap_to_erc20(1, 102),
+                Err(Psp22Error::InsufficientEscrow)
+            );
+        }
+
+        #[test]
+        fn swap_direction_round_trips() {
+            for (direction, encoded) in [(SwapDirection::ToAsset, 0u8), (SwapDirection::ToErc20, 1)]
+            {
+                assert_eq!(scale::Encode::encode(&direction), vec![encoded]);
+                assert_eq!(
+                    <SwapDirection as scale::Decode>::decode(&mut &[encoded][..]),
+                    Ok(direction)
+                );
+            }
+            assert!(<SwapDirection as scale::Decode>::decode(&mut &[2u8][..]).is_err());
+        }
+
+        #[test]
+        fn estimated_pair_storage_bytes_covers_full_config() {
+            let config = PairConfig {
+                max_swap: Some(1_000),
+                erc20_decimals_override: Some(6),
+                oracle: Some(AccountId::from([0x01; 32])),
+                max_oracle_age: Some(60),
+                ..Default::default()
+            };
+            let config_bytes = scale::Encode::encoded_size(&config);
+            assert_eq!(
+                crate::estimated_pair_storage_bytes() as usize,
+                config_bytes + 2 * crate::MAPPING_KEY_BYTES + 32 + 4
+            );
+            assert!(scale::Encode::encoded_size(&PairConfig::default()) < config_bytes);
+        }
+
+        #[test]
+        fn swap_math_holds_invariants_for_random_inputs() {
+            // A fixed-seed xorshift generator keeps failures reproducible.
+            let mut state = 0x9E37_79B9_7F4A_7C15u64;
+            let mut next = move || {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            };
+            for _ in 0..2_000 {
+                let frac_decimals = (next() % 20) as u8;
+                let frac = u128::from(next()) % 10u128.pow(u32::from(frac_decimals));
+                let (rate_numerator, rate_denominator) =
+                    crate::rate_from_parts(next() % 1_000, frac as u64, frac_decimals);
+                if rate_numerator == 0 {
+                    continue;
+                }
+                let config = PairConfig {
+                    rate_numerator,
+                    rate_denominator,
+                    fee_bps: (next() % 10_001) as u16,
+                    min_fee: Balance::from(next() % 4 * (next() >> 40)),
+                    ..Default::default()
+                };
+                let amount = Balance::from(next() >> 16);
+                let smaller = amount / 2;
+
+                let (output, fee) = config.output_and_fee(amount).unwrap();
+                // The output and fee never exceed what the input buys at the rate.
+                assert!((output + fee) * rate_denominator <= amount * rate_numerator);
+                assert!(config.output_and_fee(smaller).unwrap().0 <= output);
+                // Swapping the output back never yields more than was put in.
+                assert!(config.reverse_output_for(output).unwrap() <= amount);
+                if let Ok(input) = config.input_for(output) {
+                    assert!(input <= amount);
+                    assert!(config.output_and_fee(input).unwrap().0 >= output);
+                    if input > 0 {
+                        assert!(config.output_and_fee(input - 1).unwrap().0 < output);
+                    }
+                }
+
+                // Extreme amounts and rates must fail cleanly rather than panic.
+                let extreme = PairConfig {
+                    rate_numerator: u128::from(next()) << 64 | 1,
+                    rate_denominator: u128::from(next()) | 1,
+                    ..config
+                };
+                let huge = u128::from(next()) << 64 | u128::from(next());
+                let _ = extreme.output_and_fee(huge);
+                let _ = extreme.reverse_output_for(huge);
+                let _ = extreme.input_for(huge);
+            }
+        }
+
+        #[ink::test]
+        fn approve_many_works() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            psp22_mock::set_allowance(1, contract_id, accounts.bob, 5);
+            assert_eq!(
+                contract.approve_many(vec![(1, accounts.bob, 10), (2, accounts.charlie, 20)]),
+                Ok(())
+            );
+            assert_eq!(psp22_mock::allowance(1, contract_id, accounts.bob), 10);
+            assert_eq!(psp22_mock::allowance(2, contract_id, accounts.charlie), 20);
+
+            assert_eq!(
+                contract.approve_many(vec![(1, accounts.bob, 0); MAX_BATCH + 1]),
+                Err(Psp22Error::BatchTooLarge)
+            );
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.approve_many(vec![(1, accounts.bob, 1_000)]),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(psp22_mock::allowance(1, contract_id, accounts.bob), 10);
+        }
+
+        #[ink::test]
+        fn approve_many_reports_failing_index() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            psp22_mock::freeze(3);
+            assert_eq!(
+                contract.approve_many(vec![
+                    (1, accounts.bob, 10),
+                    (2, accounts.charlie, 20),
+                    (3, accounts.django, 30),
+                ]),
+                Err(Psp22Error::BatchItemFailed { index: 2 })
+            );
+            // The approvals before the failing one are reverted on-chain.
+            assert_eq!(psp22_mock::allowance(3, contract_id, accounts.django), 0);
+        }
+
+        #[ink::test]
+        fn swap_uses_oracle_rate() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            let oracle = AccountId::from([0x0A; 32]);
+            assert_eq!(
+                contract.set_pair_config(
+                    1,
+                    PairConfig {
+                        oracle: Some(oracle),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                contract.swap_for_asset(1, 100),
+                Err(Psp22Error::OracleFailed)
+            );
+
+            oracle_mock::set_rate(oracle, 1, (3, 2));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(150));
+            assert_eq!(psp22_mock::balance_of(1, alice), 150);
+            // The stored rate is left untouched.
+            assert_eq!(contract.pair_config_of(1).rate_numerator, 1);
+        }
+
+        #[ink::test]
+        fn stale_oracle_rate_is_rejected() {
+            let (mut contract, _) = setup();
+            let oracle = AccountId::from([0x0A; 32]);
+            assert_eq!(
+                contract.set_pair_config(
+                    1,
+                    PairConfig {
+                        oracle: Some(oracle),
+                        max_oracle_age: Some(60),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            oracle_mock::set_rate(oracle, 1, (2, 1));
+            oracle_mock::set_last_updated(oracle, 1, 1_000);
+
+            ink::env::test::set_block_timestamp::<Env>(61_000);
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(20));
+            ink::env::test::set_block_timestamp::<Env>(61_001);
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::StaleOracle));
+        }
+
+        #[ink::test]
+        fn oracle_rate_must_stay_within_band() {
+            let (mut contract, _) = setup();
+            let oracle = AccountId::from([0x0A; 32]);
+            assert_eq!(
+                contract.set_pair_config(
+                    1,
+                    PairConfig {
+                        oracle: Some(oracle),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                contract.set_reference_rate(1, Some((0, 1)), 500),
+                Err(Psp22Error::InvalidRate)
+            );
+            // The oracle rate may stray 5% from 2.
+            assert_eq!(contract.set_reference_rate(1, Some((2, 1)), 500), Ok(()));
+
+            oracle_mock::set_rate(oracle, 1, (21, 10));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(21));
+            oracle_mock::set_rate(oracle, 1, (19, 10));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(19));
+            oracle_mock::set_rate(oracle, 1, (211, 100));
+            assert_eq!(
+                contract.swap_for_asset(1, 10),
+                Err(Psp22Error::PriceOutOfBand)
+            );
+            oracle_mock::set_rate(oracle, 1, (189, 100));
+            assert_eq!(
+                contract.swap_for_asset(1, 10),
+                Err(Psp22Error::PriceOutOfBand)
+            );
+
+            assert_eq!(contract.set_reference_rate(1, None, 0), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(189));
+
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(
+                contract.set_reference_rate(1, Some((2, 1)), 500),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn large_swaps_cool_down() {
+            let (mut contract, _) = setup();
+            assert_eq!(
+                contract.set_cooldown(2, Some((100, 3))),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            assert_eq!(contract.set_cooldown(1, Some((100, 3))), Ok(()));
+
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(contract.swap_for_asset(1, 150), Ok(150));
+            for _ in 0..2 {
+                ink::env::test::advance_block::<Env>();
+                assert_eq!(
+                    contract.swap_for_asset(1, 101),
+                    Err(Psp22Error::CooldownActive)
+                );
+                // Swaps up to the threshold are not held back.
+                assert_eq!(contract.swap_for_asset(1, 50), Ok(50));
+            }
+
+            ink::env::test::advance_block::<Env>();
+            assert_eq!(contract.swap_for_asset(1, 101), Ok(101));
+            assert_eq!(
+                contract.swap_for_asset(1, 101),
+                Err(Psp22Error::CooldownActive)
+            );
+            assert_eq!(contract.set_cooldown(1, None), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 101), Ok(101));
+        }
+
+        #[ink::test]
+        fn self_destruct_mode_disables_contract_for_good() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(
+                contract.self_destruct_mode(),
+                Err(Psp22Error::EscrowNotEmpty)
+            );
+            psp22_mock::set_allowance(1, alice, contract_id, 100);
+            assert_eq!(contract.swap_to_erc20(1, 100), Ok(100));
+            assert_eq!(contract.self_destruct_mode(), Ok(()));
+            assert!(contract.is_terminated());
+            let (events_before, event_count) = (recorded_events().len(), contract.event_count());
+
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::Terminated));
+            assert_eq!(contract.swap_to_erc20(1, 10), Err(Psp22Error::Terminated));
+            assert_eq!(
+                contract.swap_batch(vec![(1, 10)], true),
+                Err(Psp22Error::Terminated)
+            );
+            assert_eq!(contract.attempt_swap_for_asset(1, 10), None);
+            assert_eq!(recorded_events().len(), events_before);
+            assert_eq!(contract.event_count(), event_count);
+            assert_eq!(contract.transfer(1, alice, 1), Err(Psp22Error::Terminated));
+            assert_eq!(contract.set_paused(false), Err(Psp22Error::Terminated));
+            assert_eq!(
+                contract.set_pair_config(1, PairConfig::default()),
+                Err(Psp22Error::Terminated)
+            );
+            assert_eq!(contract.self_destruct_mode(), Err(Psp22Error::Terminated));
+            assert!(contract.is_terminated());
+        }
+
+        #[ink::test]
+        fn circuit_breaker_pauses_on_large_outflow() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.set_circuit_breaker(Some(100), 60_000), Ok(()));
+
+            assert_eq!(contract.swap_for_asset(1, 60), Ok(60));
+            assert!(!contract.is_paused());
+            // The swap crossing the threshold still goes through.
+            assert_eq!(contract.swap_for_asset(1, 50), Ok(50));
+            assert!(contract.is_paused());
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::CircuitBreakerTripped(CircuitBreakerTripped {
+                    asset_id: 1,
+                    outflow: 110,
+                    ..
+                }))
+            ));
+            assert_eq!(contract.swap_for_asset(1, 1), Err(Psp22Error::Paused));
+
+            // Outflow is counted afresh in a new window, once the owner unpauses.
+            ink::env::test::set_block_timestamp::<Env>(60_000);
+            assert_eq!(contract.set_paused(false), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert!(!contract.is_paused());
+        }
+
+        #[ink::test]
+        fn max_event_topics_works() {
+            let (contract, _) = setup();
+            assert_eq!(contract.max_event_topics(), 4);
+        }
+
+        #[ink::test]
+        fn swap_native_for_asset_works() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            ink::env::test::set_value_transferred::<Env>(100);
+            assert_eq!(
+                contract.swap_native_for_asset(1),
+                Err(Psp22Error::WrapFailed)
+            );
+
+            erc20_mock::set_wraps_native(AccountId::from(ERC20));
+            assert_eq!(contract.swap_native_for_asset(1), Ok(100));
+            assert_eq!(psp22_mock::balance_of(1, alice), 100);
+            assert_eq!(contract_erc20_balance(), 100);
+            assert_eq!(contract.escrowed_of(1), 100);
+            // None of the caller's own ERC20 is pulled.
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 1_000);
+        }
+
+        #[ink::test]
+        fn swap_idempotent_rejects_repeated_keys() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(contract.swap_idempotent(1, 10, Some([1; 32])), Ok(10));
+            assert_eq!(
+                contract.swap_idempotent(1, 10, Some([1; 32])),
+                Err(Psp22Error::DuplicateRequest)
+            );
+            assert_eq!(contract.swap_idempotent(1, 10, Some([2; 32])), Ok(10));
+            assert_eq!(contract.swap_idempotent(1, 10, None), Ok(10));
+            assert_eq!(contract.swap_idempotent(1, 10, None), Ok(10));
+            assert_eq!(psp22_mock::balance_of(1, alice), 40);
+
+            // Another account using alice's key does not use it up for her.
+            let bob = default_accounts::<Env>().bob;
+            erc20_mock::set_balance(AccountId::from(ERC20), bob, 10);
+            erc20_mock::approve(AccountId::from(ERC20), bob, contract_id, 10);
+            ink::env::test::set_caller::<Env>(bob);
+            assert_eq!(contract.swap_idempotent(1, 10, Some([3; 32])), Ok(10));
+            ink::env::test::set_caller::<Env>(alice);
+            assert_eq!(contract.swap_idempotent(1, 10, Some([3; 32])), Ok(10));
+            assert_eq!(psp22_mock::balance_of(1, bob), 10);
+        }
+
+        #[ink::test]
+        fn withdraw_fees_follows_fee_split() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.set_fee(1, 1_000), Ok(()));
+            assert_eq!(
+                contract.set_fee_split(accounts.charlie, 7_000, accounts.django, 2_000),
+                Err(Psp22Error::InvalidSplit)
+            );
+            assert_eq!(
+                contract.set_fee_split(accounts.charlie, 7_000, accounts.django, 3_000),
+                Ok(())
+            );
+
+            // 7 of the 25 accrued fees are owed to the buyback fund, and the rounding
+            // dust to the treasury.
+            assert_eq!(contract.swap_for_asset(1, 250), Ok(225));
+            assert_eq!(contract.withdraw_fees(1), Ok(25));
+            assert_eq!(psp22_mock::balance_of(1, accounts.charlie), 18);
+            assert_eq!(psp22_mock::balance_of(1, accounts.django), 7);
+
+            assert_eq!(
+                contract.set_fee_split(accounts.eve, 10_000, accounts.django, 0),
+                Ok(())
+            );
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(90));
+            assert_eq!(contract.withdraw_fees(1), Ok(10));
+            assert_eq!(psp22_mock::balance_of(1, accounts.eve), 10);
+            assert_eq!(psp22_mock::balance_of(1, accounts.django), 7);
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.set_fee_split(accounts.bob, 5_000, accounts.bob, 5_000),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn total_accrued_fees_works() {
+            let (mut contract, contract_id) = setup();
+            psp22_mock::set_balance(2, contract_id, 1_000);
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            assert_eq!(contract.set_fee(1, 1_000), Ok(()));
+            assert_eq!(contract.set_fee(2, 500), Ok(()));
+
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(90));
+            assert_eq!(contract.swap_for_asset(2, 200), Ok(190));
+            assert_eq!(contract.total_accrued_fees(), 20);
+        }
+
+        #[ink::test]
+        fn rate_from_parts_reduces_fractions() {
+            assert_eq!(crate::rate_from_parts(1, 25, 2), (5, 4));
+            assert_eq!(crate::rate_from_parts(2, 0, 0), (2, 1));
+            assert_eq!(crate::rate_from_parts(0, 5, 1), (1, 2));
+            assert_eq!(crate::rate_from_parts(0, 5, 2), (1, 20));
+            assert_eq!(crate::rate_from_parts(3, 0, 6), (3, 1));
+            assert_eq!(crate::rate_from_parts(0, 0, 3), (0, 1));
+            assert_eq!(
+                crate::rate_from_parts(u64::MAX, 1, 19),
+                (u128::from(u64::MAX) * 10u128.pow(19) + 1, 10u128.pow(19))
+            );
+        }
+
+        #[ink::test]
+        fn remove_asset_pair_requires_empty_escrow() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(
+                contract.remove_asset_pair(1),
+                Err(Psp22Error::EscrowNotEmpty)
+            );
+
+            psp22_mock::set_allowance(1, alice, contract_id, 100);
+            assert_eq!(contract.swap_to_erc20(1, 100), Ok(100));
+            assert_eq!(contract.remove_asset_pair(1), Ok(()));
+            assert!(contract.all_pairs().is_empty());
+            assert_eq!(
+                contract.swap_for_asset(1, 100),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            assert_eq!(
+                contract.remove_asset_pair(1),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn remove_asset_pair_clears_per_asset_state() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            let erc20 = AccountId::from(ERC20);
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 12);
+            assert_eq!(contract.set_fee(1, 1_000), Ok(()));
+            assert_eq!(contract.set_cooldown(1, Some((50, 10))), Ok(()));
+            assert_eq!(contract.cache_metadata(1), Ok(()));
+            assert_eq!(contract.token_symbol_cached(1), Ok(b"AST".to_vec()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(90));
+            psp22_mock::set_balance(1, alice, 100);
+            psp22_mock::set_allowance(1, alice, contract_id, 100);
+            assert_eq!(contract.swap_to_erc20(1, 100), Ok(100));
+            assert_eq!(contract.remove_asset_pair(1), Ok(()));
+
+            assert!(contract.cooldowns.get(1).is_none());
+            assert!(contract.last_large_swap.get(1).is_none());
+            assert_eq!(contract.last_execution_rate(1), None);
+            assert_eq!(contract.twap(1), None);
+            assert!(!contract.is_metadata_cached(1));
+            assert!(contract.symbol_cache.get(1).is_none());
+            assert_eq!(contract.accrued_fees_of(1), 10);
+
+            // A pair registered again starts without the old cooldown.
+            assert_eq!(contract.create_asset_pair(1, erc20_at(erc20)), Ok(()));
+            erc20_mock::approve(erc20, alice, contract_id, 100);
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+        }
+
+        #[ink::test]
+        fn remove_asset_pairs_skips_unknown_and_respects_escrow() {
+            let (mut contract, _) = setup();
+            for asset_id in [2, 3] {
+                assert_eq!(
+                    contract.create_asset_pair(asset_id, erc20_at(AccountId::from(ERC20))),
+                    Ok(())
+                );
+            }
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(
+                contract.remove_asset_pairs(vec![2, 1]),
+                Err(Psp22Error::EscrowNotEmpty)
+            );
+            assert_eq!(contract.all_pairs().len(), 3);
+
+            assert_eq!(contract.remove_asset_pairs(vec![2, 7, 3, 2]), Ok(2));
+            assert_eq!(contract.all_pairs().len(), 1);
+            assert_eq!(contract.pair_config(2), None);
+            assert_eq!(
+                contract.remove_asset_pairs(vec![7; MAX_BATCH + 1]),
+                Err(Psp22Error::BatchTooLarge)
+            );
+        }
+
+        #[ink::test]
+        fn portfolio_value_works() {
+            let (contract, contract_id) = setup();
+            psp22_mock::set_balance(2, contract_id, 40);
+            assert_eq!(
+                contract.portfolio_value(contract_id, vec![1, 2, 3]),
+                Ok(vec![(1, 1_000), (2, 40), (3, 0)])
+            );
+            assert_eq!(
+                contract.portfolio_value(contract_id, vec![1; MAX_BATCH + 1]),
+                Err(Psp22Error::BatchTooLarge)
+            );
+        }
+
+        #[ink::test]
+        fn swap_for_exact_asset_refunds_overpayment() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(
+                contract.set_pair_config(
+                    1,
+                    PairConfig {
+                        fee_bps: 1_000,
+                        max_swap: Some(99),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                contract.swap_for_exact_asset(1, 90, 98),
+                Err(Psp22Error::SlippageExceeded)
+            );
+            // The cap applies to the 101 needed, not to the 150 offered.
+            assert_eq!(
+                contract.swap_for_exact_asset(1, 91, 150),
+                Err(Psp22Error::AboveMaxSwap)
+            );
+
+            assert_eq!(contract.swap_for_exact_asset(1, 90, 150), Ok((99, 51)));
+            assert_eq!(psp22_mock::balance_of(1, alice), 90);
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 901);
+            assert_eq!(contract.escrowed_of(1), 99);
+            assert_eq!(contract_erc20_balance(), 99);
+            assert_eq!(contract.accrued_fees_of(1), 9);
+
+            // Paying exactly what is needed leaves nothing to refund.
+            assert_eq!(contract.set_rate(1, 3, 2), Ok(()));
+            assert_eq!(contract.swap_for_exact_asset(1, 100, 74), Ok((74, 0)));
+            assert_eq!(psp22_mock::balance_of(1, alice), 190);
+            assert_eq!(contract.accrued_fees_of(1), 20);
+        }
+
+        #[ink::test]
+        fn quote_multi_hop_compounds_hops() {
+            let (mut contract, _) = setup();
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            assert_eq!(
+                contract.create_asset_pair(3, erc20_at(AccountId::from([0x20; 32]))),
+                Ok(())
+            );
+            assert_eq!(contract.set_rate(1, 3, 1), Ok(()));
+            assert_eq!(contract.set_rate(2, 1, 2), Ok(()));
+            assert_eq!(contract.set_fee(2, 1_000), Ok(()));
+
+            // 100 buys 300 of asset `1`, which sells back for 100 that buy 50 of asset
+            // `2` before the fee.
+            assert_eq!(contract.quote_multi_hop(vec![1, 2], 100), Ok(45));
+            // 100 buys 45 of asset `2`, which sell back for 90 that buy 270 of asset `1`.
+            assert_eq!(contract.quote_multi_hop(vec![2, 1], 100), Ok(270));
+            assert_eq!(contract.quote_multi_hop(vec![1], 100), Ok(300));
+            assert_eq!(
+                contract.quote_multi_hop(vec![1, 3], 100),
+                Err(Psp22Error::InvalidRoute)
+            );
+            assert_eq!(
+                contract.quote_multi_hop(vec![1, 4], 100),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            assert_eq!(
+                contract.quote_multi_hop(Vec::new(), 100),
+                Err(Psp22Error::InvalidRoute)
+            );
+            assert_eq!(contract.quote_multi_hop(vec![1; MAX_HOPS], 100), Ok(300));
+            assert_eq!(
+                contract.quote_multi_hop(vec![1; MAX_HOPS + 1], 100),
+                Err(Psp22Error::InvalidRoute)
+            );
+        }
+
+        #[ink::test]
+        fn swap_multi_hop_works() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            psp22_mock::set_balance(2, contract_id, 1_000);
+            assert_eq!(contract.set_rate(1, 3, 1), Ok(()));
+            assert_eq!(contract.set_rate(2, 1, 2), Ok(()));
+            assert_eq!(contract.set_fee(2, 1_000), Ok(()));
+
+            assert_eq!(
+                contract.swap_multi_hop(vec![1, 2], 100, 46),
+                Err(Psp22Error::SlippageExceeded)
+            );
+            assert_eq!(contract.swap_multi_hop(vec![1, 2], 100, 45), Ok(45));
+            assert_eq!(psp22_mock::balance_of(2, alice), 45);
+            assert_eq!(psp22_mock::balance_of(1, alice), 0);
+            // The 300 of asset `1` bought by the first hop are sold straight back.
+            assert_eq!(psp22_mock::balance_of(1, contract_id), 1_000);
+            assert_eq!(psp22_mock::balance_of(2, contract_id), 955);
+            assert_eq!(contract.escrowed_of(1), 0);
+            assert_eq!(contract.escrowed_of(2), 100);
+            assert_eq!(contract_erc20_balance(), 100);
+            assert_eq!(contract.accrued_fees_of(2), 5);
+            assert_eq!(contract.recent_swaps(alice).len(), 3);
+            // Every hop into an asset counts towards its execution rate and TWAP.
+            assert_eq!(contract.last_execution_rate(1), Some((300, 100)));
+            assert_eq!(contract.last_execution_rate(2), Some((45, 100)));
+            assert_eq!(
+                contract.twap(2),
+                Some((45 * TWAP_PRECISION / 100, TWAP_PRECISION))
+            );
+
+            assert_eq!(
+                contract.create_asset_pair(3, erc20_at(AccountId::from([0x20; 32]))),
+                Ok(())
+            );
+            assert_eq!(
+                contract.swap_multi_hop(vec![1, 3], 10, 0),
+                Err(Psp22Error::InvalidRoute)
+            );
+            assert_eq!(
+                contract.swap_multi_hop(vec![1; MAX_HOPS + 1], 10, 0),
+                Err(Psp22Error::InvalidRoute)
+            );
+            assert_eq!(
+                contract.swap_multi_hop(Vec::new(), 10, 0),
+                Err(Psp22Error::InvalidRoute)
+            );
+        }
+
+        #[ink::test]
+        fn swap_multi_hop_failing_mid_route_moves_nothing() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            psp22_mock::set_balance(2, contract_id, 1_000);
+            assert_eq!(contract.set_asset_paused(2, true), Ok(()));
+
+            assert_eq!(
+                contract.swap_multi_hop(vec![1, 2], 100, 0),
+                Err(Psp22Error::AssetPaused)
+            );
+            assert_eq!(
+                contract.swap_multi_hop(vec![1, 3], 100, 0),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 1_000);
+            assert_eq!(contract.escrowed_of(1), 0);
+            assert_eq!(psp22_mock::balance_of(2, alice), 0);
+            assert!(contract.recent_swaps(alice).is_empty());
+        }
+
+        #[ink::test]
+        fn swap_and_call_notifies_recipient() {
+            let (mut contract, _) = setup();
+            let receiver = AccountId::from([0x0B; 32]);
+            assert_eq!(
+                contract.swap_and_call(1, 100, receiver),
+                Err(Psp22Error::CallbackFailed)
+            );
+
+            receiver_mock::register(receiver);
+            assert_eq!(contract.swap_and_call(1, 50, receiver), Ok(50));
+            assert_eq!(receiver_mock::received(receiver), vec![(1, 50)]);
+        }
+
+        #[ink::test]
+        fn swap_for_asset_raw_transfers_without_receipt() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            let events_before = recorded_events().len();
+
+            assert_eq!(contract.swap_for_asset_raw(1, 100), Ok(()));
+            assert_eq!(psp22_mock::balance_of(1, alice), 100);
+            assert_eq!(psp22_mock::balance_of(1, contract_id), 900);
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 900);
+            assert_eq!(contract_erc20_balance(), 100);
+            assert_eq!(contract.escrowed_of(1), 100);
+            assert!(contract.recent_swaps(alice).is_empty());
+            assert_eq!(contract.last_execution_rate(1), None);
+
+            let events = recorded_events();
+            assert_eq!(events.len(), events_before + 2);
+            assert!(matches!(
+                &events[events_before],
+                Event::EscrowChanged(event) if event.amount == 100 && event.new_total == 100
+            ));
+            assert!(matches!(
+                &events[events_before + 1],
+                Event::RawSwap(event) if event.amount_in == 100 && event.amount_out == 100
+            ));
+        }
+
+        #[ink::test]
+        fn swap_batch_handles_unknown_assets_per_mode() {
+            let (mut contract, _) = setup();
+            let swaps = vec![(1, 100), (7, 50), (1, 20)];
+            assert_eq!(
+                contract.swap_batch(swaps.clone(), true),
+                Err(Psp22Error::BatchItemFailed { index: 1 })
+            );
+            assert_eq!(contract.escrowed_of(1), 0);
+
+            assert_eq!(contract.swap_batch(swaps, false), Ok(vec![100, 0, 20]));
+            assert_eq!(contract.escrowed_of(1), 120);
+            assert_eq!(
+                contract.swap_batch(vec![(1, 30), (1, 40)], true),
+                Ok(vec![30, 40])
+            );
+            assert_eq!(
+                contract.swap_batch(vec![(1, 1); MAX_BATCH + 1], false),
+                Err(Psp22Error::BatchTooLarge)
+            );
+            // Alice has approved too little ERC20 for the third swap.
+            assert_eq!(
+                contract.swap_batch(vec![(1, 10), (7, 10), (1, 5_000)], false),
+                Err(Psp22Error::BatchItemFailed { index: 2 })
+            );
+        }
+
+        #[ink::test]
+        fn batch_messages_are_bounded() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(
+                contract.balances_of(1, vec![contract_id, accounts.alice]),
+                Ok(vec![1_000, 0])
+            );
+            assert_eq!(
+                contract
+                    .balances_of(1, vec![accounts.bob; MAX_BATCH])
+                    .map(|balances| balances.len()),
+                Ok(MAX_BATCH)
+            );
+            assert_eq!(
+                contract.balances_of(1, vec![accounts.bob; MAX_BATCH + 1]),
+                Err(Psp22Error::BatchTooLarge)
+            );
+            assert_eq!(
+                contract.approve_many(vec![(1, accounts.bob, 10); MAX_BATCH]),
+                Ok(())
+            );
+            assert_eq!(
+                contract.approve_many(vec![(1, accounts.bob, 10); MAX_BATCH + 1]),
+                Err(Psp22Error::BatchTooLarge)
+            );
+        }
+
+        #[ink::test]
+        fn balance_of_scaled_works() {
+            let (contract, contract_id) = setup();
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 12);
+            assert_eq!(contract.balance_of_scaled(1, contract_id), Ok((1_000, 12)));
+        }
+
+        #[ink::test]
+        fn set_rate_works() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.set_rate(1, 5, 4), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::RateChanged(RateChanged {
+                    asset_id: 1,
+                    old_num: 1,
+                    old_den: 1,
+                    new_num: 5,
+                    new_den: 4,
+                    ..
+                }))
+            ));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(125));
+
+            assert_eq!(contract.set_rate(1, 1, 0), Err(Psp22Error::InvalidRate));
+            assert_eq!(contract.set_rate(1, 0, 1), Err(Psp22Error::InvalidRate));
+            assert_eq!(
+                contract.set_rate(2, 1, 1),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            assert_eq!(contract.pair_config_of(1).rate_numerator, 5);
+        }
+
+        #[ink::test]
+        fn over_long_metadata_is_rejected() {
+            let (contract, _) = setup();
+            psp22_mock::set_metadata(1, &[b'a'; MAX_METADATA_LEN], b"AST", 12);
+            assert_eq!(contract.token_name(1), Ok(vec![b'a'; MAX_METADATA_LEN]));
+
+            psp22_mock::set_metadata(1, &[b'a'; MAX_METADATA_LEN + 1], b"AST", 12);
+            assert_eq!(contract.token_name(1), Err(Psp22Error::MetadataTooLong));
+            assert_eq!(contract.token_symbol(1), Ok(b"AST".to_vec()));
+        }
+
+        #[ink::test]
+        fn swap_for_asset_split_works() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(
+                contract.swap_for_asset_split(1, 100, accounts.bob, 10_001, accounts.charlie),
+                Err(Psp22Error::InvalidSplit)
+            );
+
+            // 33.33% of 101 is 33.6633, so the dust goes to Charlie.
+            assert_eq!(
+                contract.swap_for_asset_split(1, 101, accounts.bob, 3_333, accounts.charlie),
+                Ok(101)
+            );
+            assert_eq!(psp22_mock::balance_of(1, accounts.bob), 33);
+            assert_eq!(psp22_mock::balance_of(1, accounts.charlie), 68);
+            assert_eq!(psp22_mock::balance_of(1, accounts.alice), 0);
+        }
+
+        #[ink::test]
+        fn is_metadata_cached_works() {
+            let (mut contract, _) = setup();
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 12);
+            assert!(!contract.is_metadata_cached(1));
+            assert_eq!(contract.cache_metadata(1), Ok(()));
+            assert!(contract.is_metadata_cached(1));
+
+            // Cached metadata is served without asking the runtime again.
+            psp22_mock::set_metadata(1, b"Renamed", b"RNM", 6);
+            assert_eq!(contract.token_name(1), Ok(b"Asset".to_vec()));
+            assert_eq!(contract.token_symbol(1), Ok(b"AST".to_vec()));
+            assert_eq!(contract.token_decimals(1), Ok(12));
+
+            psp22_mock::set_metadata(2, &[b'a'; MAX_METADATA_LEN + 1], b"AST", 12);
+            assert_eq!(contract.cache_metadata(2), Err(Psp22Error::MetadataTooLong));
+            assert!(!contract.is_metadata_cached(2));
+
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(contract.cache_metadata(1), Err(Psp22Error::NotOwner));
+            assert_eq!(contract.invalidate_metadata(1), Err(Psp22Error::NotOwner));
+            assert_eq!(contract.token_name(1), Ok(b"Asset".to_vec()));
+        }
+
+        #[ink::test]
+        fn cached_metadata_can_be_refreshed_and_invalidated() {
+            let (mut contract, _) = setup();
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 12);
+            assert_eq!(contract.cache_metadata(1), Ok(()));
+            assert_eq!(contract.token_symbol_cached(1), Ok(b"AST".to_vec()));
+
+            psp22_mock::set_metadata(1, b"Renamed", b"RNM", 6);
+            assert_eq!(contract.cache_metadata(1), Ok(()));
+            assert_eq!(contract.token_decimals(1), Ok(6));
+
+            psp22_mock::set_metadata(1, b"Again", b"AGN", 8);
+            assert_eq!(contract.invalidate_metadata(1), Ok(()));
+            assert!(!contract.is_metadata_cached(1));
+            assert_eq!(contract.symbol_cache.get(1), None);
+            assert_eq!(contract.token_name(1), Ok(b"Again".to_vec()));
+            assert_eq!(contract.token_decimals(1), Ok(8));
+        }
+
+        #[ink::test]
+        fn clear_rate_limits_unblocks_accounts() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.set_swap_interval(1_000), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::RateLimited));
+
+            assert_eq!(contract.clear_rate_limits(), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::RateLimited));
+
+            ink::env::test::set_block_timestamp::<Env>(1_000);
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(contract.clear_rate_limits(), Err(Psp22Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn swap_with_referral_pays_kickback() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.set_fee(1, 1_000), Ok(()));
+            assert_eq!(
+                contract.set_referral_cut(10_001),
+                Err(Psp22Error::InvalidFee)
+            );
+            assert_eq!(contract.set_referral_cut(2_500), Ok(()));
+
+            // Of the 20 fee, a quarter goes to the referrer.
+            assert_eq!(
+                contract.swap_with_referral(1, 200, Some(accounts.django)),
+                Ok(180)
+            );
+            assert_eq!(psp22_mock::balance_of(1, accounts.alice), 180);
+            assert_eq!(psp22_mock::balance_of(1, accounts.django), 5);
+            assert_eq!(contract.referral_earnings_of(accounts.django), 5);
+            assert_eq!(contract.accrued_fees_of(1), 15);
+
+            assert_eq!(contract.swap_with_referral(1, 100, None), Ok(90));
+            assert_eq!(contract.accrued_fees_of(1), 25);
+
+            assert_eq!(
+                contract.swap_with_referral(1, 100, Some(accounts.alice)),
+                Err(Psp22Error::SelfReferral)
+            );
+            assert_eq!(psp22_mock::balance_of(1, accounts.alice), 270);
+            assert_eq!(contract.referral_earnings_of(accounts.alice), 0);
+            assert_eq!(contract.accrued_fees_of(1), 25);
+        }
+
+        #[ink::test]
+        fn swap_preconditions_works() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.swap_preconditions(1, 1_000), Ok(()));
+            assert_eq!(
+                contract.swap_preconditions(1, 1_001),
+                Err(Psp22Error::InsufficientAllowance)
+            );
+            assert_eq!(
+                contract.swap_preconditions(2, 10),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+
+            assert_eq!(
+                contract.set_pair_config(
+                    1,
+                    PairConfig {
+                        max_swap: Some(100),
+                        ..Default::default()
+                    }
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                contract.swap_preconditions(1, 101),
+                Err(Psp22Error::AboveMaxSwap)
+            );
+
+            assert_eq!(contract.set_blocked(accounts.alice, true), Ok(()));
+            assert_eq!(contract.swap_preconditions(1, 10), Err(Psp22Error::Blocked));
+            assert_eq!(contract.set_blocked(accounts.alice, false), Ok(()));
+
+            assert_eq!(contract.set_allowlist_enabled(true), Ok(()));
+            assert_eq!(
+                contract.swap_preconditions(1, 10),
+                Err(Psp22Error::NotAllowlisted)
+            );
+            assert_eq!(contract.set_allowlist_enabled(false), Ok(()));
+
+            assert_eq!(contract.set_paused(true), Ok(()));
+            assert_eq!(contract.swap_preconditions(1, 10), Err(Psp22Error::Paused));
+        }
+
+        #[ink::test]
+        fn pause_and_enable_gates_are_independent() {
+            let (mut contract, contract_id) = setup();
+            psp22_mock::set_balance(2, contract_id, 1_000);
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+
+            assert_eq!(contract.set_asset_paused(1, true), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Err(Psp22Error::AssetPaused));
+            assert_eq!(contract.swap_for_asset(2, 10), Ok(10));
+            assert_eq!(contract.set_asset_paused(1, false), Ok(()));
+
+            assert_eq!(contract.set_asset_enabled(1, false), Ok(()));
+            assert_eq!(
+                contract.swap_for_asset(1, 10),
+                Err(Psp22Error::AssetDisabled)
+            );
+            assert_eq!(contract.swap_for_asset(2, 10), Ok(10));
+            // Unpausing does not re-enable the asset.
+            assert_eq!(contract.set_asset_paused(1, false), Ok(()));
+            assert_eq!(
+                contract.swap_for_asset(1, 10),
+                Err(Psp22Error::AssetDisabled)
+            );
+            assert_eq!(contract.set_asset_enabled(1, true), Ok(()));
+
+            assert_eq!(contract.set_paused(true), Ok(()));
+            assert_eq!(contract.swap_for_asset(2, 10), Err(Psp22Error::Paused));
+            assert_eq!(contract.set_paused(false), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(
+                contract.set_asset_paused(1, true),
+                Err(Psp22Error::NotPauser)
+            );
+            assert_eq!(
+                contract.set_asset_enabled(1, false),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn rescue_native_works() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            fund_native(500);
+            let charlie_native = get_account_balance::<Env>(accounts.charlie).unwrap();
+
+            assert_eq!(
+                contract.rescue_native(accounts.charlie, 501),
+                Err(Psp22Error::NativeTransferFailed)
+            );
+            assert_eq!(contract.rescue_native(accounts.charlie, 200), Ok(()));
+            assert_eq!(
+                get_account_balance::<Env>(accounts.charlie),
+                Ok(charlie_native + 200)
+            );
+            assert_eq!(get_account_balance::<Env>(contract_id), Ok(300));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::NativeRescued(NativeRescued { amount: 200, .. }))
+            ));
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.rescue_native(accounts.bob, 1),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn last_execution_rate_is_recorded() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.last_execution_rate(1), None);
+            assert_eq!(contract.set_rate(1, 3, 2), Ok(()));
+            assert_eq!(contract.set_fee(1, 1_000), Ok(()));
+
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(135));
+            assert_eq!(contract.last_execution_rate(1), Some((135, 100)));
+            assert_eq!(contract.swap_for_asset(1, 7), Ok(9));
+            assert_eq!(contract.last_execution_rate(1), Some((9, 7)));
+        }
+
+        #[ink::test]
+        fn rescue_native_leaves_reserve() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            fund_native(500);
+            assert_eq!(contract.set_native_reserve(100), Ok(()));
+
+            assert_eq!(
+                contract.rescue_native(accounts.charlie, 401),
+                Err(Psp22Error::ReserveViolation)
+            );
+            assert_eq!(contract.rescue_native(accounts.charlie, 400), Ok(()));
+            assert_eq!(get_account_balance::<Env>(contract_id), Ok(100));
+            assert_eq!(
+                contract.rescue_native(accounts.charlie, 1),
+                Err(Psp22Error::ReserveViolation)
+            );
+
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.set_native_reserve(0), Err(Psp22Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn erc20_pull_failures_are_distinguished() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(
+                contract.swap_for_asset(1, 1_001),
+                Err(Psp22Error::InsufficientAllowance)
+            );
+            erc20_mock::set_balance(AccountId::from(ERC20), alice, 10);
+            assert_eq!(
+                contract.swap_for_asset(1, 11),
+                Err(Psp22Error::TransferFromFailed)
+            );
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+        }
+
+        #[ink::test]
+        fn new_with_pair_works() {
+            // Only the mocks prepared by `setup` are needed.
+            setup();
+            let alice = default_accounts::<Env>().alice;
+            assert!(matches!(
+                Psp22Extension::new_with_pair(
+                    AccountId::from(ERC20),
+                    1,
+                    PairConfig {
+                        rate_denominator: 0,
+                        ..Default::default()
+                    }
+                ),
+                Err(Psp22Error::InvalidRate)
+            ));
+
+            let Ok(mut contract) = Psp22Extension::new_with_pair(
+                AccountId::from(ERC20),
+                1,
+                PairConfig {
+                    rate_numerator: 2,
+                    ..Default::default()
+                },
+            ) else {
+                panic!("a valid pair config is accepted");
+            };
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(20));
+            assert_eq!(psp22_mock::balance_of(1, alice), 20);
+        }
+
+        #[ink::test]
+        fn config_version_bumps_on_each_change() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.pair_config_version(1), Some(0));
+            assert_eq!(contract.pair_config_version(2), None);
+
+            assert_eq!(contract.set_rate(1, 3, 2), Ok(()));
+            assert_eq!(contract.pair_config_version(1), Some(1));
+            assert_eq!(contract.set_fee(1, 30), Ok(()));
+            assert_eq!(contract.pair_config_version(1), Some(2));
+            assert_eq!(contract.set_rate(1, 3, 2), Ok(()));
+            assert_eq!(contract.pair_config_version(1), Some(3));
+
+            // A version passed in is not taken over.
+            let config = PairConfig {
+                config_version: 100,
+                ..Default::default()
+            };
+            assert_eq!(contract.set_pair_config(1, config), Ok(()));
+            assert_eq!(contract.pair_config_version(1), Some(4));
+            assert_eq!(contract.set_fee(1, 10_001), Err(Psp22Error::InvalidFee));
+            assert_eq!(contract.pair_config_version(1), Some(4));
+        }
+
+        #[ink::test]
+        fn pair_config_works() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.pair_config(1), Some(PairConfig::default()));
+            assert_eq!(contract.pair_config(2), None);
+
+            let config = PairConfig {
+                max_swap: Some(500),
+                allow_partial: true,
+                rate_numerator: 3,
+                rate_denominator: 2,
+                fee_bps: 30,
+                ..Default::default()
+            };
+            assert_eq!(contract.set_pair_config(1, config.clone()), Ok(()));
+            assert_eq!(
+                contract.pair_config(1),
+                Some(PairConfig {
+                    config_version: 1,
+                    ..config
+                })
+            );
+        }
+
+        #[ink::test]
+        fn fee_on_transfer_tokens_swap_the_received_amount() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            // The token burns 2% of every transferred amount.
+            erc20_mock::set_transfer_fee(AccountId::from(ERC20), 200);
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(98));
+            assert_eq!(psp22_mock::balance_of(1, alice), 98);
+            assert_eq!(contract.escrowed_of(1), 98);
+            assert_eq!(contract_erc20_balance(), 98);
+            assert_eq!(contract.recent_swaps(alice)[0].amount_in, 98);
+
+            erc20_mock::set_transfer_fee(AccountId::from(ERC20), 10_000);
+            assert_eq!(
+                contract.swap_for_asset(1, 100),
+                Err(Psp22Error::UnexpectedTransferAmount)
+            );
+        }
+
+        #[ink::test]
+        fn attempt_swap_refunds_when_transfer_fee_zeroes_output() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(contract.set_rate(1, 1, 10), Ok(()));
+            // 10 ERC20 buy 1 of the asset, but only the 9 left after the 10% transfer
+            // fee arrive, which buy nothing.
+            erc20_mock::set_transfer_fee(AccountId::from(ERC20), 1_000);
+            assert_eq!(contract.attempt_swap_for_asset(1, 10), None);
+            // The 9 received are sent back, the refund's own fee rounding to zero.
+            assert_eq!(erc20_mock::balance_of(AccountId::from(ERC20), alice), 999);
+            assert_eq!(contract_erc20_balance(), 0);
+            assert_eq!(contract.escrowed_of(1), 0);
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::SwapFailed(SwapFailed { reason, .. }))
+                    if *reason == Psp22Error::ZeroOutput.discriminant()
+            ));
+        }
+
+        #[ink::test]
+        fn can_fill_works() {
+            let (mut contract, contract_id) = setup();
+            assert_eq!(contract.can_fill(1, 1_000), Ok(true));
+            assert_eq!(contract.can_fill(1, 1_001), Ok(false));
+            assert_eq!(contract.can_fill(2, 10), Err(Psp22Error::AssetPairNotFound));
+
+            psp22_mock::set_balance(1, contract_id, 50);
+            assert_eq!(contract.can_fill(1, 50), Ok(true));
+            assert_eq!(contract.can_fill(1, 51), Ok(false));
+            assert_eq!(contract.set_rate(1, 1, 2), Ok(()));
+            assert_eq!(contract.can_fill(1, 100), Ok(true));
+        }
+
+        #[ink::test]
+        fn liquidity_events_are_emitted() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            psp22_mock::set_balance(1, accounts.bob, 500);
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            let events_before = recorded_events().len();
+            assert_eq!(
+                contract.deposit_liquidity(1, 500),
+                Err(Psp22Error::TotalSupplyFailed)
+            );
+            assert_eq!(recorded_events().len(), events_before);
+
+            psp22_mock::set_allowance(1, accounts.bob, contract_id, 500);
+            assert_eq!(contract.deposit_liquidity(1, 500), Ok(()));
+            assert_eq!(psp22_mock::balance_of(1, contract_id), 1_500);
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::LiquidityDeposited(LiquidityDeposited {
+                    asset_id: 1,
+                    amount: 500,
+                    provider,
+                    ..
+                })) if *provider == accounts.bob
+            ));
+
+            ink::env::test::set_caller::<Env>(accounts.alice);
+            assert_eq!(contract.set_fee(1, 1_000), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(90));
+            // 1_410 is held, 10 of which are accrued fees.
+            assert_eq!(
+                contract.withdraw_liquidity(1, 1_401, accounts.charlie),
+                Err(Psp22Error::InsufficientLiquidity)
+            );
+            assert_eq!(
+                contract.withdraw_liquidity(1, 1_400, accounts.charlie),
+                Ok(())
+            );
+            assert_eq!(psp22_mock::balance_of(1, accounts.charlie), 1_400);
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::LiquidityWithdrawn(LiquidityWithdrawn {
+                    asset_id: 1,
+                    amount: 1_400,
+                    to,
+                    ..
+                })) if *to == accounts.charlie
+            ));
+        }
+
+        #[ink::test]
+        fn ownership_proposals_expire() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.set_ownership_grace_period(Some(2)), Ok(()));
+            assert_eq!(contract.propose_owner(accounts.bob), Ok(()));
+            ink::env::test::advance_block::<Env>();
+            ink::env::test::advance_block::<Env>();
+
+            ink::env::test::set_caller::<Env>(accounts.charlie);
+            assert_eq!(
+                contract.accept_ownership(),
+                Err(Psp22Error::NotPendingOwner)
+            );
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.accept_ownership(), Ok(()));
+            assert_eq!(contract.set_fee_recipient(accounts.bob), Ok(()));
+
+            assert_eq!(contract.propose_owner(accounts.charlie), Ok(()));
+            for _ in 0..3 {
+                ink::env::test::advance_block::<Env>();
+            }
+            ink::env::test::set_caller::<Env>(accounts.charlie);
+            assert_eq!(
+                contract.accept_ownership(),
+                Err(Psp22Error::ProposalExpired)
+            );
+            assert_eq!(
+                contract.set_fee_recipient(accounts.charlie),
+                Err(Psp22Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn roles_works() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(
+                contract.roles(),
+                (accounts.alice, accounts.alice, accounts.alice)
+            );
+            assert_eq!(contract.set_pauser(accounts.bob), Ok(()));
+            assert_eq!(contract.set_fee_recipient(accounts.charlie), Ok(()));
+            assert_eq!(
+                contract.roles(),
+                (accounts.alice, accounts.bob, accounts.charlie)
+            );
+        }
+
+        #[ink::test]
+        fn pairs_with_unsupported_decimal_gap_are_rejected() {
+            let (mut contract, contract_id) = setup();
+            let wide = AccountId::from([0x30; 32]);
+            psp22_mock::set_balance(3, contract_id, 1_000);
+            psp22_mock::set_metadata(3, b"Asset", b"AST", 0);
+            erc20_mock::set_decimals(wide, 39);
+            assert_eq!(
+                contract.create_asset_pair(3, erc20_at(wide)),
+                Err(Psp22Error::DecimalsOutOfRange)
+            );
+            assert_eq!(contract.pair_config(3), None);
+
+            erc20_mock::set_decimals(wide, 38);
+            assert_eq!(contract.create_asset_pair(3, erc20_at(wide)), Ok(()));
+        }
+
+        #[ink::test]
+        fn reserved_asset_ids_are_rejected() {
+            let (mut contract, _) = setup();
+            let erc20 = AccountId::from(ERC20);
+            assert_eq!(contract.reserved_range(), (0, 0));
+            assert_eq!(contract.set_reserved_range(10, 20), Ok(()));
+            for asset_id in [10, 15, 19] {
+                psp22_mock::set_metadata(asset_id, b"System", b"SYS", 18);
+                assert_eq!(
+                    contract.create_asset_pair(asset_id, erc20_at(erc20)),
+                    Err(Psp22Error::ReservedAssetId)
+                );
+                assert_eq!(contract.pair_config(asset_id), None);
+            }
+            for asset_id in [9, 20] {
+                psp22_mock::set_metadata(asset_id, b"Asset", b"AST", 18);
+                assert_eq!(
+                    contract.create_asset_pair(asset_id, erc20_at(erc20)),
+                    Ok(())
+                );
+            }
+
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(contract.set_reserved_range(0, 0), Err(Psp22Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn events_carry_contiguous_sequence_numbers() {
+            let (mut contract, _) = setup();
+            let alice = default_accounts::<Env>().alice;
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(contract.set_rate(1, 2, 1), Ok(()));
+            assert_eq!(contract.swap_for_asset_raw(1, 10), Ok(()));
+            assert_eq!(contract.withdraw_liquidity(1, 100, alice), Ok(()));
+
+            let seqs: Vec<u64> = recorded_events()
+                .iter()
+                .map(|event| match event {
+                    Event::RateChanged(event) => event.event_seq,
+                    Event::EscrowChanged(event) => event.event_seq,
+                    Event::RawSwap(event) => event.event_seq,
+                    Event::CircuitBreakerTripped(event) => event.event_seq,
+                    Event::SwapFailed(event) => event.event_seq,
+                    Event::EscrowSynced(event) => event.event_seq,
+                    Event::LiquidityDeposited(event) => event.event_seq,
+                    Event::LiquidityWithdrawn(event) => event.event_seq,
+                    Event::NativeRescued(event) => event.event_seq,
+                    Event::LockForceReset(event) => event.event_seq,
+                })
+                .collect();
+            assert_eq!(seqs, (0..5).collect::<Vec<_>>());
+            assert_eq!(contract.event_count(), 5);
+        }
+
+        #[ink::test]
+        fn now_reports_block_and_timestamp() {
+            let (contract, _) = setup();
+            let (block, _) = contract.now();
+            ink::env::test::advance_block::<Env>();
+            ink::env::test::set_block_timestamp::<Env>(42_000);
+            assert_eq!(contract.now(), (block + 1, 42_000));
+        }
+
+        #[ink::test]
+        fn min_fee_applies_when_share_rounds_lower() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.set_fee(1, 30), Ok(()));
+            assert_eq!(contract.set_min_fee(1, 5), Ok(()));
+            // 30 basis points of 100 round down to nothing, so the floor is charged.
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(95));
+            assert_eq!(contract.accrued_fees_of(1), 5);
+            // The floor never takes more than the whole output.
+            assert_eq!(contract.swap_for_asset(1, 3), Err(Psp22Error::ZeroOutput));
+
+            assert_eq!(contract.set_fee(1, 1_000), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(90));
+            assert_eq!(contract.accrued_fees_of(1), 15);
+
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(contract.set_min_fee(1, 0), Err(Psp22Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn timed_approvals_expire() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.swap_for_asset(1, 600), Ok(600));
+            // The holder approves this contract through the runtime, then times it.
+            psp22_mock::set_allowance(1, accounts.alice, contract_id, 500);
+            assert_eq!(
+                contract.set_timed_approval(1, contract_id, 501, 10_000),
+                Err(Psp22Error::InsufficientAllowance)
+            );
+            assert_eq!(
+                contract.set_timed_approval(1, accounts.bob, 500, 10_000),
+                Err(Psp22Error::UnsupportedSpender)
+            );
+            assert_eq!(
+                contract.set_timed_approval(1, contract_id, 500, 10_000),
+                Ok(())
+            );
+
+            ink::env::test::set_block_timestamp::<Env>(9_999);
+            assert_eq!(contract.swap_to_erc20(1, 200), Ok(200));
+            assert_eq!(contract.deposit_liquidity(1, 100), Ok(()));
+            assert_eq!(contract.swap_preconditions(1, 100), Ok(()));
+
+            ink::env::test::set_block_timestamp::<Env>(10_000);
+            assert_eq!(
+                contract.swap_to_erc20(1, 100),
+                Err(Psp22Error::ApprovalExpired)
+            );
+            assert_eq!(
+                contract.deposit_liquidity(1, 100),
+                Err(Psp22Error::ApprovalExpired)
+            );
+            assert_eq!(
+                contract.swap_preconditions(1, 100),
+                Err(Psp22Error::ApprovalExpired)
+            );
+            assert_eq!(
+                contract.swap_for_asset(1, 100),
+                Err(Psp22Error::ApprovalExpired)
+            );
+            assert_eq!(psp22_mock::allowance(1, accounts.alice, contract_id), 200);
+
+            // Other holders' approvals are not affected.
+            psp22_mock::set_balance(1, accounts.bob, 100);
+            psp22_mock::set_allowance(1, accounts.bob, contract_id, 100);
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.deposit_liquidity(1, 100), Ok(()));
+        }
+
+        #[ink::test]
+        fn balance_delta_is_signed() {
+            let (contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            psp22_mock::set_balance(1, accounts.bob, 400);
+            assert_eq!(
+                contract.balance_delta(1, contract_id, accounts.bob),
+                Ok(600)
+            );
+            assert_eq!(
+                contract.balance_delta(1, accounts.bob, contract_id),
+                Ok(-600)
+            );
+            assert_eq!(contract.balance_delta(1, accounts.bob, accounts.bob), Ok(0));
+
+            psp22_mock::set_balance(1, accounts.bob, Balance::MAX);
+            assert_eq!(
+                contract.balance_delta(1, accounts.bob, accounts.charlie),
+                Err(Psp22Error::Overflow)
+            );
+        }
+
+        #[ink::test]
+        fn transfer_ownership_honours_two_step_flag() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.transfer_ownership(accounts.bob, true), Ok(()));
+            assert_eq!(contract.roles().0, accounts.alice);
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(contract.accept_ownership(), Ok(()));
+            assert_eq!(contract.roles().0, accounts.bob);
+
+            assert_eq!(contract.propose_owner(accounts.django), Ok(()));
+            assert_eq!(contract.transfer_ownership(accounts.charlie, false), Ok(()));
+            assert_eq!(contract.roles().0, accounts.charlie);
+            assert_eq!(
+                contract.transfer_ownership(accounts.bob, false),
+                Err(Psp22Error::NotOwner)
+            );
+            ink::env::test::set_caller::<Env>(accounts.django);
+            assert_eq!(
+                contract.accept_ownership(),
+                Err(Psp22Error::NotPendingOwner)
+            );
+        }
+
+        #[ink::test]
+        fn inverse_rate_swaps_parts() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.set_rate(1, 5, 4), Ok(()));
+            assert_eq!(contract.inverse_rate(1), Some((4, 5)));
+            assert_eq!(contract.inverse_rate(2), None);
+        }
+
+        #[ink::test]
+        fn cached_contract_id_matches_live_one() {
+            let (contract, contract_id) = setup();
+            assert_eq!(contract.own_account_id(), contract_id);
+            assert_eq!(contract.contract_id, ink::env::account_id::<Env>());
+        }
+
+        #[ink::test]
+        fn is_owner_works() {
+            let (contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert!(contract.is_owner(accounts.alice));
+            assert!(!contract.is_owner(accounts.bob));
+        }
+
+        #[ink::test]
+        fn unit_mode_scales_for_decimals() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            psp22_mock::set_balance(1, contract_id, 1_000_000);
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 6);
+            erc20_mock::set_decimals(AccountId::from(ERC20), 3);
+            erc20_mock::set_balance(AccountId::from(ERC20), alice, 10_000);
+            erc20_mock::approve(AccountId::from(ERC20), alice, contract_id, 10_000);
+
+            assert_eq!(contract.quote_multi_hop(vec![1], 1_000), Ok(1_000));
+            assert_eq!(contract.set_unit_mode(1, true), Ok(()));
+            // One whole ERC20, 10^3 of its smallest unit, buys one whole asset.
+            assert_eq!(contract.quote_multi_hop(vec![1], 1_000), Ok(1_000_000));
+            assert_eq!(contract.swap_for_asset(1, 1), Ok(1_000));
+
+            // Fewer asset decimals scale the output down instead.
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 1);
+            assert_eq!(contract.quote_multi_hop(vec![1], 1_000), Ok(10));
+        }
+
+        #[ink::test]
+        fn revoke_allowances_zeroes_each_allowance() {
+            let (mut contract, contract_id) = setup();
+            let accounts = default_accounts::<Env>();
+            psp22_mock::set_allowance(1, contract_id, accounts.bob, 10);
+            psp22_mock::set_allowance(2, contract_id, accounts.charlie, 20);
+            assert_eq!(
+                contract.revoke_allowances(vec![(1, accounts.bob), (2, accounts.charlie)]),
+                Ok(())
+            );
+            assert_eq!(psp22_mock::allowance(1, contract_id, accounts.bob), 0);
+            assert_eq!(psp22_mock::allowance(2, contract_id, accounts.charlie), 0);
+
+            psp22_mock::freeze(2);
+            assert_eq!(
+                contract.revoke_allowances(vec![(1, accounts.bob), (2, accounts.bob)]),
+                Err(Psp22Error::BatchItemFailed { index: 1 })
+            );
+            assert_eq!(
+                contract.revoke_allowances(vec![(1, accounts.bob); MAX_BATCH + 1]),
+                Err(Psp22Error::BatchTooLarge)
+            );
+
+            psp22_mock::set_allowance(1, contract_id, accounts.bob, 10);
+            ink::env::test::set_caller::<Env>(accounts.bob);
+            assert_eq!(
+                contract.revoke_allowances(vec![(1, accounts.bob)]),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(psp22_mock::allowance(1, contract_id, accounts.bob), 10);
+        }
+
+        #[ink::test]
+        fn lock_is_released_and_force_unlock_is_owner_only() {
+            let (mut contract, _) = setup();
+            let receiver = AccountId::from([0x0B; 32]);
+            assert_eq!(
+                contract.swap_and_call(1, 10, receiver),
+                Err(Psp22Error::CallbackFailed)
+            );
+            assert!(!contract.is_locked());
+            receiver_mock::register(receiver);
+            assert_eq!(contract.swap_and_call(1, 10, receiver), Ok(10));
+            assert!(!contract.is_locked());
+
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(contract.force_unlock(), Err(Psp22Error::NotOwner));
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().alice);
+            assert_eq!(contract.force_unlock(), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::LockForceReset(_))
+            ));
+            assert!(!contract.is_locked());
+            assert_eq!(contract.swap_for_asset(1, 10), Ok(10));
+        }
+
+        #[ink::test]
+        fn token_symbol_cached_stores_only_the_symbol() {
+            let (mut contract, _) = setup();
+            psp22_mock::set_metadata(1, b"Asset", b"AST", 12);
+            assert_eq!(contract.token_symbol_cached(1), Ok(b"AST".to_vec()));
+            assert_eq!(contract.symbol_cache.get(1), Some(b"AST".to_vec()));
+            assert!(!contract.is_metadata_cached(1));
+
+            psp22_mock::set_metadata(1, b"Renamed", b"RNM", 6);
+            assert_eq!(contract.token_symbol_cached(1), Ok(b"AST".to_vec()));
+            assert_eq!(contract.token_name(1), Ok(b"Renamed".to_vec()));
+        }
+
+        #[ink::test]
+        fn default_recipient_receives_swap_output() {
+            let (mut contract, _) = setup();
+            let accounts = default_accounts::<Env>();
+            assert_eq!(contract.set_default_recipient(Some(accounts.eve)), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(psp22_mock::balance_of(1, accounts.eve), 100);
+            assert_eq!(psp22_mock::balance_of(1, accounts.alice), 0);
+
+            assert_eq!(contract.swap_for_asset_raw(1, 10), Ok(()));
+            assert_eq!(contract.swap_with_referral(1, 10, None), Ok(10));
+            assert_eq!(contract.attempt_swap_for_asset(1, 10), Some(10));
+            assert_eq!(contract.swap_idempotent(1, 10, None), Ok(10));
+            assert_eq!(contract.swap_for_exact_asset(1, 10, 10), Ok((10, 0)));
+            assert_eq!(contract.swap_multi_hop(vec![1], 10, 0), Ok(10));
+            assert_eq!(psp22_mock::balance_of(1, accounts.eve), 160);
+            assert_eq!(psp22_mock::balance_of(1, accounts.alice), 0);
+
+            assert_eq!(contract.set_default_recipient(None), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 50), Ok(50));
+            assert_eq!(psp22_mock::balance_of(1, accounts.alice), 50);
+            assert_eq!(psp22_mock::balance_of(1, accounts.eve), 160);
+        }
+
+        #[ink::test]
+        fn unique_backing_rejects_shared_erc20() {
+            let (mut contract, _) = setup();
+            let erc20 = AccountId::from(ERC20);
+            assert_eq!(contract.create_asset_pair(2, erc20_at(erc20)), Ok(()));
+
+            assert_eq!(contract.set_unique_backing(true), Ok(()));
+            assert_eq!(
+                contract.create_asset_pair(3, erc20_at(erc20)),
+                Err(Psp22Error::Erc20AlreadyBound)
+            );
+            assert_eq!(contract.pair_config(3), None);
+            // Re-registering an asset with its own ERC20 is not cross-wiring.
+            assert_eq!(
+                contract.create_asset_pair(4, erc20_at(AccountId::from([0x40; 32]))),
+                Ok(())
+            );
+            assert_eq!(
+                contract.create_asset_pair(4, erc20_at(AccountId::from([0x40; 32]))),
+                Ok(())
+            );
+        }
+
+        #[ink::test]
+        fn estimate_fees_sums_per_swap_fees() {
+            let (mut contract, _) = setup();
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+            assert_eq!(contract.set_fee(1, 100), Ok(()));
+            assert_eq!(contract.set_fee(2, 250), Ok(()));
+            assert_eq!(contract.set_rate(2, 2, 1), Ok(()));
+            let swaps = vec![(1, 1_000), (2, 1_000), (1, 50)];
+            let expected: Balance = swaps
+                .iter()
+                .map(|&(asset_id, amount)| {
+                    contract
+                        .pair_config(asset_id)
+                        .unwrap()
+                        .output_and_fee(amount)
+                        .unwrap()
+                        .1
+                })
+                .sum();
+            assert_eq!(expected, 10 + 50);
+            assert_eq!(contract.estimate_fees(swaps), Ok(expected));
+            assert_eq!(contract.escrowed_of(1), 0);
+
+            assert_eq!(
+                contract.estimate_fees(vec![(1, 10), (7, 10)]),
+                Err(Psp22Error::AssetPairNotFound)
+            );
+            assert_eq!(
+                contract.estimate_fees(vec![(1, 1); MAX_BATCH + 1]),
+                Err(Psp22Error::BatchTooLarge)
+            );
+        }
+
+        #[ink::test]
+        fn twap_weights_rates_by_time() {
+            let (mut contract, _) = setup();
+            assert_eq!(contract.twap(1), None);
+            ink::env::test::set_block_timestamp::<Env>(1_000);
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(contract.twap(1), Some((TWAP_PRECISION, TWAP_PRECISION)));
+
+            // A rate of 1 stands for 1s, then a rate of 2 for 3s: 7/4 on average.
+            ink::env::test::set_block_timestamp::<Env>(2_000);
+            assert_eq!(contract.set_rate(1, 2, 1), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(200));
+            ink::env::test::set_block_timestamp::<Env>(5_000);
+            let (numerator, denominator) = contract.twap(1).unwrap();
+            assert_eq!(numerator * 4, denominator * 7);
+            assert_eq!(contract.twap(2), None);
+        }
+
+        #[ink::test]
+        fn twap_averages_over_bounded_window() {
+            let (mut contract, _) = setup();
+            ink::env::test::set_block_timestamp::<Env>(1_000);
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            ink::env::test::set_block_timestamp::<Env>(2_000);
+            assert_eq!(contract.set_rate(1, 2, 1), Ok(()));
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(200));
+
+            // Once the window has moved past the first swap only the second one counts.
+            ink::env::test::set_block_timestamp::<Env>(2_000 + TWAP_WINDOW);
+            let (numerator, denominator) = contract.twap(1).unwrap();
+            assert_eq!(numerator, denominator * 2);
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(200));
+            assert_eq!(
+                contract.twap_points.get(1).map(|points| points.len()),
+                Some(2)
+            );
+
+            for _ in 0..MAX_TWAP_POINTS {
+                assert_eq!(contract.swap_for_asset(1, 1), Ok(2));
+            }
+            assert_eq!(
+                contract.twap_points.get(1).map(|points| points.len()),
+                Some(MAX_TWAP_POINTS)
+            );
+
+            contract.twap_points.insert(1, &vec![(0, Balance::MAX, 1)]);
+            // Rates too large for a `u128` are clamped rather than failing the query.
+            assert_eq!(
+                contract.twap(1),
+                Some((u128::MAX, TWAP_PRECISION * u128::from(TWAP_WINDOW)))
+            );
+        }
+
+        #[ink::test]
+        fn create_asset_pair_is_owner_only() {
+            let (mut contract, _) = setup();
+            ink::env::test::set_caller::<Env>(default_accounts::<Env>().bob);
+            assert_eq!(
+                contract.create_asset_pair(1, erc20_at(AccountId::from([0x66; 32]))),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(
+                contract.create_asset_pair(2, erc20_at(AccountId::from(ERC20))),
+                Err(Psp22Error::NotOwner)
+            );
+            assert_eq!(contract.pair_config(2), None);
+        }
+
+        #[ink::test]
+        fn create_asset_pair_refuses_rebinding_escrowed_pair() {
+            let (mut contract, contract_id) = setup();
+            let alice = default_accounts::<Env>().alice;
+            let worthless = AccountId::from([0x66; 32]);
+            assert_eq!(contract.swap_for_asset(1, 100), Ok(100));
+            assert_eq!(
+                contract.create_asset_pair(1, erc20_at(worthless)),
+                Err(Psp22Error::EscrowNotEmpty)
+            );
+            // Registering the same ERC20 again changes nothing and is allowed.
+            assert_eq!(
+                contract.create_asset_pair(1, erc20_at(AccountId::from(ERC20))),
+                Ok(())
+            );
+
+            psp22_mock::set_allowance(1, alice, contract_id, 100);
+            assert_eq!(contract.swap_to_erc20(1, 100), Ok(100));
+            assert_eq!(contract.create_asset_pair(1, erc20_at(worthless)), Ok(()));
         }
     }
 }